@@ -1,8 +1,6 @@
+use chrono::NaiveTime;
 use serde::{Deserialize, Serialize};
-use solana_sdk::signature::Keypair;
-use std::fs;
-use std::io::Write;
-use std::path::Path;
+use std::{fs, time::Duration};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct BuyConfig {
@@ -10,6 +8,85 @@ pub struct BuyConfig {
     pub slippage: f64,
     pub use_jito: bool,
     pub jito_tip: f64,
+    #[serde(default = "default_prio_fee")]
+    pub prio_fee: f64,
+    // Skip tokens with no pump.fun curve, pool or Jupiter route instead of trying Jupiter
+    #[serde(default)]
+    pub skip_unknown_market: bool,
+    #[serde(default)]
+    pub retry: RetryConfig,
+}
+
+// How trades that fail to land are sent again. Each retry raises the priority fee by
+// `prio_fee_step`, retries after a slippage failure also raise the slippage by `slippage_step`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RetryConfig {
+    // Attempts including the first one, the default of 1 disables retries
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+    #[serde(default)]
+    pub slippage_step: f64,
+    #[serde(default)]
+    pub max_slippage: f64,
+    #[serde(default)]
+    pub prio_fee_step: f64,
+    #[serde(default)]
+    pub max_prio_fee: f64,
+    // Keep retrying until this many seconds after the first attempt, instead of `max_attempts`
+    #[serde(default)]
+    pub deadline_secs: Option<u64>,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: default_max_attempts(),
+            slippage_step: 0.0,
+            max_slippage: 0.0,
+            prio_fee_step: 0.0,
+            max_prio_fee: 0.0,
+            deadline_secs: None,
+        }
+    }
+}
+
+impl RetryConfig {
+    // Slippage after `bumps` raises, caps below the configured slippage never lower it
+    pub fn slippage(&self, base: f64, bumps: u32) -> f64 {
+        (base + self.slippage_step * bumps as f64).min(self.max_slippage.max(base))
+    }
+
+    pub fn prio_fee(&self, base: f64, bumps: u32) -> f64 {
+        (base + self.prio_fee_step * bumps as f64).min(self.max_prio_fee.max(base))
+    }
+
+    // Longest a trade can keep retrying when one attempt takes at most `attempt`
+    pub fn max_duration(&self, attempt: Duration) -> Duration {
+        match self.deadline_secs {
+            // The last attempt may start right before the deadline
+            Some(deadline) => Duration::from_secs(deadline) + attempt,
+            None => attempt * self.max_attempts.max(1),
+        }
+    }
+
+    // Whether another attempt may follow `attempts` failed ones, `elapsed` after the first
+    pub fn allows(&self, attempts: u32, elapsed: Duration) -> bool {
+        match self.deadline_secs {
+            Some(deadline) => elapsed < Duration::from_secs(deadline),
+            None => attempts < self.max_attempts,
+        }
+    }
+}
+
+fn default_max_attempts() -> u32 {
+    1
+}
+
+// Sell `fraction` of the position once it is up `at` percent
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TakeProfitLevel {
+    pub at: f64,
+    pub fraction: f64,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -17,17 +94,442 @@ pub struct SellConfig {
     pub slippage: f64,
     pub use_jito: bool,
     pub jito_tip: f64,
+    #[serde(default = "default_prio_fee")]
+    pub prio_fee: f64,
     pub auto_sell: bool,
     pub sell_at: f64,
+    #[serde(default)]
+    pub stop_loss: Option<f64>,
+    #[serde(default)]
+    pub trailing_stop: Option<f64>,
+    #[serde(default)]
+    pub take_profit: Vec<TakeProfitLevel>,
+    // Set `deadline_secs` so a sell keeps trying until the position is out
+    #[serde(default)]
+    pub retry: RetryConfig,
+}
+
+impl SellConfig {
+    // The take-profit ladder sorted by level, falling back to selling everything at `sell_at`
+    pub fn take_profit_levels(&self) -> Vec<TakeProfitLevel> {
+        if self.take_profit.is_empty() {
+            return vec![TakeProfitLevel {
+                at: self.sell_at,
+                fraction: 1.0,
+            }];
+        }
+        let mut levels = self.take_profit.clone();
+        levels.sort_by(|a, b| a.at.total_cmp(&b.at));
+        levels
+    }
+}
+
+fn default_prio_fee() -> f64 {
+    0.0001
+}
+
+// Sell settings a user entry may override, unset fields use the global `SellConfig`
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SellOverrides {
+    pub slippage: Option<f64>,
+    pub use_jito: Option<bool>,
+    pub jito_tip: Option<f64>,
+    pub prio_fee: Option<f64>,
+    pub auto_sell: Option<bool>,
+    pub sell_at: Option<f64>,
+    pub stop_loss: Option<f64>,
+    pub trailing_stop: Option<f64>,
+    pub take_profit: Option<Vec<TakeProfitLevel>>,
+    pub retry: Option<RetryConfig>,
+}
+
+// A monitored user with optional overrides, unset fields use the global `BuyConfig`
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct UserConfig {
+    pub name: String,
+    pub amount: Option<f64>,
+    pub slippage: Option<f64>,
+    pub use_jito: Option<bool>,
+    pub jito_tip: Option<f64>,
+    pub prio_fee: Option<f64>,
+    pub skip_unknown_market: Option<bool>,
+    pub retry: Option<RetryConfig>,
+    pub sell: Option<SellOverrides>,
+    #[serde(default)]
+    pub filter: FilterConfig,
+    // Name of the wallet this user's buys always go through
+    pub wallet: Option<String>,
+}
+
+// Local time window, `end` before `start` wraps past midnight
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ActiveHours {
+    // "HH:MM"
+    pub start: String,
+    pub end: String,
+}
+
+impl ActiveHours {
+    pub fn bounds(&self) -> Result<(NaiveTime, NaiveTime), String> {
+        let parse = |t: &str| {
+            NaiveTime::parse_from_str(t, "%H:%M").map_err(|_| format!("invalid active hour {t}"))
+        };
+        Ok((parse(&self.start)?, parse(&self.end)?))
+    }
+}
+
+// Rules a tweet has to pass before it can trigger a buy
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct FilterConfig {
+    // At least one of these has to appear, case-insensitive
+    #[serde(default)]
+    pub require_keywords: Vec<String>,
+    #[serde(default)]
+    pub ignore_keywords: Vec<String>,
+    // With or without the `$`
+    #[serde(default)]
+    pub required_cashtag: Option<String>,
+    #[serde(default)]
+    pub ignore_replies: bool,
+    #[serde(default)]
+    pub ignore_retweets: bool,
+    #[serde(default)]
+    pub ignore_quotes: bool,
+    #[serde(default)]
+    pub active_hours: Option<ActiveHours>,
+    #[serde(default)]
+    pub max_tweet_age_secs: Option<u64>,
+    // Minimum time between two buys triggered by this user
+    #[serde(default)]
+    pub cooldown_secs: Option<u64>,
+}
+
+// Either a plain username or a user with overrides
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum UserEntry {
+    Name(String),
+    Config(Box<UserConfig>),
+}
+
+impl UserEntry {
+    pub fn name(&self) -> &str {
+        match self {
+            UserEntry::Name(name) => name,
+            UserEntry::Config(config) => &config.name,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DedupPolicy {
+    // Buy on every signal, like before buys were deduplicated
+    #[default]
+    Always,
+    // Never buy the same mint twice
+    Once,
+    // Buy again once `window_secs` have passed since the last buy
+    Window,
+    // Buy again only after the previous position was fully sold
+    AfterClose,
+    // Keep buying while the open cost basis stays under `max_exposure_sol`
+    ScaleIn,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct DedupConfig {
+    #[serde(default)]
+    pub policy: DedupPolicy,
+    #[serde(default)]
+    pub window_secs: u64,
+    #[serde(default)]
+    pub max_exposure_sol: f64,
+}
+
+// What to do when a pre-trade safety check fails
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckAction {
+    Block,
+    Warn,
+    #[default]
+    Ignore,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SafetyConfig {
+    #[serde(default)]
+    pub mint_authority: CheckAction,
+    #[serde(default)]
+    pub freeze_authority: CheckAction,
+    #[serde(default)]
+    pub holder_concentration: CheckAction,
+    #[serde(default = "default_top_holders")]
+    pub top_holders: usize,
+    #[serde(default = "default_max_top_holders_pct")]
+    pub max_top_holders_pct: f64,
+    #[serde(default)]
+    pub token_age: CheckAction,
+    #[serde(default)]
+    pub min_token_age_secs: u64,
+    #[serde(default)]
+    pub lp_burned: CheckAction,
+    #[serde(default = "default_min_lp_burned_pct")]
+    pub min_lp_burned_pct: f64,
+}
+
+impl Default for SafetyConfig {
+    fn default() -> Self {
+        Self {
+            mint_authority: CheckAction::default(),
+            freeze_authority: CheckAction::default(),
+            holder_concentration: CheckAction::default(),
+            top_holders: default_top_holders(),
+            max_top_holders_pct: default_max_top_holders_pct(),
+            token_age: CheckAction::default(),
+            min_token_age_secs: 0,
+            lp_burned: CheckAction::default(),
+            min_lp_burned_pct: default_min_lp_burned_pct(),
+        }
+    }
+}
+
+fn default_top_holders() -> usize {
+    10
+}
+
+fn default_max_top_holders_pct() -> f64 {
+    50.0
+}
+
+fn default_min_lp_burned_pct() -> f64 {
+    90.0
+}
+
+// Account the web monitor logs in with
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TwitterConfig {
+    // Username, email or phone number entered on the first login step
+    pub identifier: String,
+    #[serde(default)]
+    pub password: Option<String>,
+    // File holding only the password, used when `password` is not set
+    #[serde(default)]
+    pub password_file: Option<String>,
+    // Answer to the alternate identifier and LoginAcid challenges, the account email or phone
+    #[serde(default)]
+    pub email: Option<String>,
+    // Base32 secret of the authenticator app, for accounts with two-factor login
+    #[serde(default)]
+    pub totp_secret: Option<String>,
+    // Unfollow everyone else and follow the configured users after login
+    #[serde(default = "default_sync_follows")]
+    pub sync_follows: bool,
+    // Cookies of the last login, reused until Twitter rejects them
+    #[serde(default = "default_session_file")]
+    pub session_file: String,
+}
+
+impl TwitterConfig {
+    pub fn password(&self) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        if let Some(password) = &self.password {
+            return Ok(password.clone());
+        }
+        let path = self
+            .password_file
+            .as_ref()
+            .ok_or("twitter.password or twitter.password_file must be set")?;
+        let password = fs::read_to_string(path)
+            .map_err(|e| format!("Unable to read Twitter password file {path}: {e}"))?;
+        Ok(password.trim().to_string())
+    }
+}
+
+fn default_sync_follows() -> bool {
+    true
+}
+
+fn default_session_file() -> String {
+    "twitter_session.json".into()
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum XApiMode {
+    // Poll every user's timeline
+    #[default]
+    Poll,
+    // One filtered stream with a `from:` rule per user
+    Stream,
+}
+
+// Official X API v2 access, used instead of the web client when present
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct XApiConfig {
+    pub bearer_token: String,
+    #[serde(default)]
+    pub mode: XApiMode,
+    #[serde(default = "default_poll_interval_secs")]
+    pub poll_interval_secs: u64,
+}
+
+fn default_poll_interval_secs() -> u64 {
+    15
+}
+
+// A trading wallet, the first one listed is the default
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WalletConfig {
+    pub name: String,
+    // Solana CLI JSON array, base58 private key or seed phrase, moved into a keystore on first start
+    pub keypair_file: String,
+    // Only used for seed phrases
+    #[serde(default)]
+    pub derivation_path: Option<String>,
+}
+
+// How buys of users without a wallet of their own are spread over the wallets
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum WalletAssignment {
+    // Always the first wallet
+    #[default]
+    Default,
+    // Each buy goes through the next wallet in turn
+    RoundRobin,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Config {
     pub rpc_url: String,
-    pub users: Vec<String>,
+    pub users: Vec<UserEntry>,
     pub license: String,
     pub buy_config: BuyConfig,
     pub sell_config: SellConfig,
+    #[serde(default)]
+    pub paper_trading: bool,
+    #[serde(default)]
+    pub dedup: DedupConfig,
+    #[serde(default)]
+    pub safety: SafetyConfig,
+    #[serde(default)]
+    pub twitter: Option<TwitterConfig>,
+    #[serde(default)]
+    pub x_api: Option<XApiConfig>,
+    // Without wallets, a single "main" wallet is read from keypair.json
+    #[serde(default)]
+    pub wallets: Vec<WalletConfig>,
+    #[serde(default)]
+    pub wallet_assignment: WalletAssignment,
+    // Fail on a missing wallet file instead of creating a new wallet there
+    #[serde(default)]
+    pub refuse_wallet_generation: bool,
+}
+
+impl Config {
+    fn user_config(&self, user: &str) -> Option<&UserConfig> {
+        self.users.iter().find_map(|entry| match entry {
+            UserEntry::Config(config) if config.name.eq_ignore_ascii_case(user) => {
+                Some(config.as_ref())
+            }
+            _ => None,
+        })
+    }
+
+    pub fn buy_config_for(&self, user: &str) -> BuyConfig {
+        let global = &self.buy_config;
+        let Some(overrides) = self.user_config(user) else {
+            return global.clone();
+        };
+
+        BuyConfig {
+            amount: overrides.amount.unwrap_or(global.amount),
+            slippage: overrides.slippage.unwrap_or(global.slippage),
+            use_jito: overrides.use_jito.unwrap_or(global.use_jito),
+            jito_tip: overrides.jito_tip.unwrap_or(global.jito_tip),
+            prio_fee: overrides.prio_fee.unwrap_or(global.prio_fee),
+            skip_unknown_market: overrides
+                .skip_unknown_market
+                .unwrap_or(global.skip_unknown_market),
+            retry: overrides
+                .retry
+                .clone()
+                .unwrap_or_else(|| global.retry.clone()),
+        }
+    }
+
+    // The configured wallets, or the single "main" wallet in keypair.json
+    pub fn wallet_configs(&self) -> Vec<WalletConfig> {
+        if self.wallets.is_empty() {
+            return vec![WalletConfig {
+                name: "main".into(),
+                keypair_file: "keypair.json".into(),
+                derivation_path: None,
+            }];
+        }
+        self.wallets.clone()
+    }
+
+    // Settings that parse but cannot work, checked before anything starts
+    pub fn validate(&self) -> Result<(), String> {
+        if self.dedup.policy == DedupPolicy::ScaleIn && self.dedup.max_exposure_sol <= 0.0 {
+            return Err("The scale_in dedup policy needs a positive max_exposure_sol".into());
+        }
+        let wallets = self.wallet_configs();
+        for entry in self.users.iter() {
+            let UserEntry::Config(user) = entry else {
+                continue;
+            };
+            if let Some(wallet) = &user.wallet {
+                if !wallets.iter().any(|w| &w.name == wallet) {
+                    return Err(format!("Unknown wallet {wallet} for user {}", user.name));
+                }
+            }
+            if let Some(hours) = &user.filter.active_hours {
+                hours
+                    .bounds()
+                    .map_err(|e| format!("User {}: {e}, use HH:MM", user.name))?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn wallet_for(&self, user: &str) -> Option<&str> {
+        self.user_config(user).and_then(|c| c.wallet.as_deref())
+    }
+
+    pub fn filter_for(&self, user: &str) -> FilterConfig {
+        self.user_config(user)
+            .map(|c| c.filter.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn sell_config_for(&self, user: &str) -> SellConfig {
+        let global = &self.sell_config;
+        let Some(overrides) = self.user_config(user).and_then(|c| c.sell.as_ref()) else {
+            return global.clone();
+        };
+
+        SellConfig {
+            slippage: overrides.slippage.unwrap_or(global.slippage),
+            use_jito: overrides.use_jito.unwrap_or(global.use_jito),
+            jito_tip: overrides.jito_tip.unwrap_or(global.jito_tip),
+            prio_fee: overrides.prio_fee.unwrap_or(global.prio_fee),
+            auto_sell: overrides.auto_sell.unwrap_or(global.auto_sell),
+            sell_at: overrides.sell_at.unwrap_or(global.sell_at),
+            stop_loss: overrides.stop_loss.or(global.stop_loss),
+            trailing_stop: overrides.trailing_stop.or(global.trailing_stop),
+            take_profit: overrides
+                .take_profit
+                .clone()
+                .unwrap_or_else(|| global.take_profit.clone()),
+            retry: overrides
+                .retry
+                .clone()
+                .unwrap_or_else(|| global.retry.clone()),
+        }
+    }
 }
 
 pub fn load_config(path: &str) -> Option<Config> {
@@ -44,15 +546,154 @@ pub fn load_or_create_config(path: &str) -> Config {
     }
 }
 
-pub fn generate_keypair_if_not_exists(file_path: &str) -> Keypair {
-    if Path::new(file_path).exists() {
-        let data = fs::read(file_path).expect("Unable to read file");
-        Keypair::from_bytes(&data).expect("Unable to parse keypair")
-    } else {
-        let keypair = Keypair::new();
-        let serialized = keypair.to_bytes();
-        let mut file = fs::File::create(file_path).expect("Unable to create file");
-        file.write_all(&serialized).expect("Unable to write data");
-        keypair
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    // A minimal config with `extra` merged into its top level
+    fn config(extra: serde_json::Value) -> Config {
+        let mut config = json!({
+            "rpc_url": "http://localhost:8899",
+            "users": [],
+            "license": "",
+            "buy_config": { "amount": 0.1, "slippage": 10.0, "use_jito": false, "jito_tip": 0.0 },
+            "sell_config": {
+                "slippage": 10.0,
+                "use_jito": false,
+                "jito_tip": 0.0,
+                "auto_sell": false,
+                "sell_at": 0.0
+            },
+        });
+        for (key, value) in extra.as_object().unwrap() {
+            config[key] = value.clone();
+        }
+        serde_json::from_value(config).unwrap()
+    }
+
+    #[test]
+    fn unknown_user_wallet() {
+        let wallets = json!([
+            { "name": "main", "keypair_file": "main.json" },
+            { "name": "snipe", "keypair_file": "snipe.json" },
+        ]);
+        let known = config(json!({
+            "wallets": wallets,
+            "users": ["alice", { "name": "bob", "wallet": "snipe" }],
+        }));
+        assert!(known.validate().is_ok());
+
+        let unknown = config(json!({
+            "wallets": wallets,
+            "users": [{ "name": "bob", "wallet": "snip" }],
+        }));
+        assert!(unknown.validate().is_err());
+
+        // Without wallets only the implicit "main" wallet exists
+        let implicit = config(json!({ "users": [{ "name": "bob", "wallet": "main" }] }));
+        assert!(implicit.validate().is_ok());
+    }
+
+    #[test]
+    fn invalid_active_hours() {
+        let user = |start: &str, end: &str| {
+            config(json!({
+                "users": [{ "name": "bob", "filter": { "active_hours": { "start": start, "end": end } } }],
+            }))
+        };
+        assert!(user("22:00", "06:30").validate().is_ok());
+        assert!(user("9:00", "17:00").validate().is_ok());
+        assert!(user("25:00", "06:00").validate().is_err());
+        assert!(user("22:00", "6pm").validate().is_err());
+    }
+
+    #[test]
+    fn dedup_policy() {
+        // Configs written before deduplication keep buying every signal
+        assert_eq!(config(json!({})).dedup.policy, DedupPolicy::Always);
+
+        let scale_in = |max_exposure_sol: f64| {
+            config(json!({
+                "dedup": { "policy": "scale_in", "max_exposure_sol": max_exposure_sol },
+            }))
+        };
+        assert!(scale_in(0.0).validate().is_err());
+        assert!(scale_in(-1.0).validate().is_err());
+        assert!(scale_in(0.5).validate().is_ok());
+    }
+
+    fn retry() -> RetryConfig {
+        RetryConfig {
+            max_attempts: 3,
+            slippage_step: 5.0,
+            max_slippage: 20.0,
+            prio_fee_step: 0.001,
+            max_prio_fee: 0.003,
+            deadline_secs: None,
+        }
+    }
+
+    #[test]
+    fn retry_slippage() {
+        let retry = retry();
+        assert_eq!(retry.slippage(10.0, 0), 10.0);
+        assert_eq!(retry.slippage(10.0, 1), 15.0);
+        assert_eq!(retry.slippage(10.0, 2), 20.0);
+        assert_eq!(retry.slippage(10.0, 5), 20.0);
+        // A cap below the base slippage never lowers it
+        assert_eq!(retry.slippage(30.0, 2), 30.0);
+    }
+
+    #[test]
+    fn retry_prio_fee() {
+        let retry = retry();
+        assert_eq!(retry.prio_fee(0.001, 0), 0.001);
+        assert_eq!(retry.prio_fee(0.001, 1), 0.002);
+        assert_eq!(retry.prio_fee(0.001, 10), 0.003);
+        assert_eq!(retry.prio_fee(0.005, 3), 0.005);
+        // Without steps every attempt pays the base fee
+        assert_eq!(RetryConfig::default().prio_fee(0.001, 4), 0.001);
+    }
+
+    #[test]
+    fn retry_duration() {
+        let attempt = Duration::from_secs(100);
+        assert_eq!(retry().max_duration(attempt), Duration::from_secs(300));
+        let deadline = RetryConfig {
+            deadline_secs: Some(30),
+            ..retry()
+        };
+        assert_eq!(deadline.max_duration(attempt), Duration::from_secs(130));
+    }
+
+    #[test]
+    fn retry_disabled_by_default() {
+        // Configs written before retries existed never send a trade twice
+        assert!(!RetryConfig::default().allows(1, Duration::ZERO));
+    }
+
+    #[test]
+    fn retry_attempts() {
+        let retry = retry();
+        assert!(retry.allows(1, Duration::ZERO));
+        assert!(retry.allows(2, Duration::from_secs(600)));
+        assert!(!retry.allows(3, Duration::ZERO));
+        let once = RetryConfig {
+            max_attempts: 1,
+            ..retry
+        };
+        assert!(!once.allows(1, Duration::ZERO));
+    }
+
+    #[test]
+    fn retry_deadline() {
+        // A deadline replaces the attempt limit
+        let retry = RetryConfig {
+            deadline_secs: Some(30),
+            ..retry()
+        };
+        assert!(retry.allows(10, Duration::from_secs(29)));
+        assert!(!retry.allows(1, Duration::from_secs(30)));
     }
 }
@@ -0,0 +1,383 @@
+use std::{error::Error, fmt, str::FromStr, time::Duration};
+
+use solana_client::{nonblocking::rpc_client::RpcClient, rpc_config::RpcTransactionConfig};
+use solana_sdk::{
+    clock::MAX_PROCESSING_AGE, commitment_config::CommitmentConfig, instruction::InstructionError,
+    pubkey::Pubkey, signature::Signature, transaction::TransactionError,
+};
+use solana_transaction_status::{
+    EncodedTransaction, TransactionConfirmationStatus, UiMessage, UiTransactionEncoding,
+};
+use tokio::time::{sleep, Instant};
+
+use crate::pricing::{PUMPFUN_PROGRAM_ID, RAYDIUM_AMM_PROGRAM_ID};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+// Longer than a blockhash lives, for when the block height cannot be read
+const GIVE_UP_AFTER: Duration = Duration::from_secs(120);
+
+const JUPITER_PROGRAM_ID: &str = "JUP6LkbZbjS1jKKwapdHNy74zcZ3tLUJoi5QNyVTaV4";
+
+// Where a submitted transaction is in its life, the last three are final
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TxStage {
+    Processed,
+    Confirmed,
+    Finalized,
+    // Landed with an error, decoded where the program is known
+    Failed(Failure),
+    // Its blockhash ran out before it landed
+    Expired,
+}
+
+impl TxStage {
+    pub fn is_final(&self) -> bool {
+        matches!(
+            self,
+            TxStage::Finalized | TxStage::Failed(_) | TxStage::Expired
+        )
+    }
+}
+
+impl fmt::Display for TxStage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TxStage::Processed => write!(f, "processed"),
+            TxStage::Confirmed => write!(f, "confirmed"),
+            TxStage::Finalized => write!(f, "finalized"),
+            TxStage::Failed(failure) => write!(f, "failed: {}", failure.reason),
+            TxStage::Expired => write!(f, "expired"),
+        }
+    }
+}
+
+// What went wrong with a trade, deciding how it is retried
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureClass {
+    // The blockhash ran out, the trade never landed
+    Expired,
+    Slippage,
+    ComputeExhausted,
+    InsufficientFunds,
+    // The token left the market the trade was routed to, e.g. its pump.fun curve completed
+    MarketChanged,
+    // The engine failed after the transaction may have been broadcast, sending it again
+    // could trade twice
+    MaybeSent,
+    Other,
+}
+
+impl FailureClass {
+    // Classify an error returned while building or sending, usually a failed simulation
+    pub fn of_message(message: &str) -> Self {
+        let message = message.to_lowercase();
+        if message.contains("insufficient") || message.contains("no record of a prior credit") {
+            FailureClass::InsufficientFunds
+        } else if message.contains("bondingcurvecomplete")
+            || message.contains("bonding curve complete")
+            // pump.fun 6005
+            || message.contains("0x1775")
+        {
+            FailureClass::MarketChanged
+        } else if message.contains("blockhash") {
+            FailureClass::Expired
+        } else if message.contains("slippage")
+            // Jupiter 6001, pump.fun 6002 and 6003
+            || ["0x1771", "0x1772", "0x1773"].iter().any(|code| message.contains(code))
+        {
+            FailureClass::Slippage
+        } else if message.contains("computational budget exceeded")
+            || message.contains("exceeded cus")
+        {
+            FailureClass::ComputeExhausted
+        } else {
+            FailureClass::Other
+        }
+    }
+}
+
+// Whether an engine error shows the transaction never left, a failed preflight simulation
+// or blockhash fetch. Only such errors are safe to send again.
+pub fn failed_before_broadcast(message: &str) -> bool {
+    let message = message.to_lowercase();
+    message.contains("simulation failed")
+        || message.contains("failed to simulate")
+        || message.contains("blockhash")
+}
+
+impl fmt::Display for FailureClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            FailureClass::Expired => "expired",
+            FailureClass::Slippage => "slippage",
+            FailureClass::ComputeExhausted => "compute exhausted",
+            FailureClass::InsufficientFunds => "insufficient funds",
+            FailureClass::MarketChanged => "market changed",
+            FailureClass::MaybeSent => "possibly sent",
+            FailureClass::Other => "error",
+        };
+        write!(f, "{name}")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Failure {
+    pub class: FailureClass,
+    pub reason: String,
+}
+
+// Follows one signature, reporting each stage it reaches once
+pub struct Tracker<'a> {
+    rpc_client: &'a RpcClient,
+    signature: Signature,
+    submitted: Instant,
+    // Block height after which the transaction can no longer land
+    expires_after: u64,
+    stage: Option<TxStage>,
+}
+
+impl<'a> Tracker<'a> {
+    // Start tracking right after the transaction was sent at `submitted`
+    pub async fn new(
+        rpc_client: &'a RpcClient,
+        signature: &str,
+        submitted: Instant,
+    ) -> Result<Tracker<'a>, Box<dyn Error + Send + Sync>> {
+        let signature = Signature::from_str(signature)?;
+        // The engine fetched its blockhash before sending, so it expires no later than this
+        let expires_after = rpc_client.get_block_height().await? + MAX_PROCESSING_AGE as u64;
+        Ok(Self {
+            rpc_client,
+            signature,
+            submitted,
+            expires_after,
+            stage: None,
+        })
+    }
+
+    // The next stage and the time since submission, None once a final stage was reported
+    pub async fn next(&mut self) -> Option<(TxStage, Duration)> {
+        if self.stage.as_ref().is_some_and(|s| s.is_final()) {
+            return None;
+        }
+        loop {
+            if let Some(stage) = self.poll().await {
+                if self.stage.as_ref() != Some(&stage) {
+                    self.stage = Some(stage.clone());
+                    return Some((stage, self.submitted.elapsed()));
+                }
+            }
+            sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    async fn poll(&self) -> Option<TxStage> {
+        let status = match self
+            .rpc_client
+            .get_signature_statuses(&[self.signature])
+            .await
+        {
+            Ok(response) => response.value.into_iter().next().flatten(),
+            Err(e) => {
+                tracing::warn!("Unable to get status of {}: {e}", self.signature);
+                // An RPC that stays down must not keep the trade waiting forever
+                return (self.submitted.elapsed() > GIVE_UP_AFTER).then_some(TxStage::Expired);
+            }
+        };
+
+        let Some(status) = status else {
+            // Not seen, or dropped with its fork, check whether it can still land
+            return match self.rpc_client.get_block_height().await {
+                Ok(height) if height > self.expires_after => Some(TxStage::Expired),
+                _ if self.submitted.elapsed() > GIVE_UP_AFTER => Some(TxStage::Expired),
+                _ => None,
+            };
+        };
+
+        let stage = match status.confirmation_status {
+            Some(TransactionConfirmationStatus::Finalized) => TxStage::Finalized,
+            Some(TransactionConfirmationStatus::Confirmed) => TxStage::Confirmed,
+            Some(TransactionConfirmationStatus::Processed) | None => TxStage::Processed,
+        };
+        // An error seen at processed may still be on a fork that gets dropped
+        match status.err {
+            Some(err) if stage != TxStage::Processed => {
+                let program = failed_program(self.rpc_client, &self.signature, &err).await;
+                Some(TxStage::Failed(decode_error(&err, program.as_ref())))
+            }
+            _ => Some(stage),
+        }
+    }
+}
+
+// Program of the top level instruction that failed, read from the landed transaction
+async fn failed_program(
+    rpc_client: &RpcClient,
+    signature: &Signature,
+    err: &TransactionError,
+) -> Option<Pubkey> {
+    let TransactionError::InstructionError(index, _) = err else {
+        return None;
+    };
+    let config = RpcTransactionConfig {
+        encoding: Some(UiTransactionEncoding::Json),
+        commitment: Some(CommitmentConfig::confirmed()),
+        max_supported_transaction_version: Some(0),
+    };
+    let tx = rpc_client
+        .get_transaction_with_config(signature, config)
+        .await
+        .ok()?;
+    let EncodedTransaction::Json(tx) = tx.transaction.transaction else {
+        return None;
+    };
+    let UiMessage::Raw(message) = tx.message else {
+        return None;
+    };
+    let instruction = message.instructions.get(*index as usize)?;
+    let program = message
+        .account_keys
+        .get(instruction.program_id_index as usize)?;
+    Pubkey::from_str(program).ok()
+}
+
+// A short reason for a failed transaction, naming the error of known programs
+pub fn decode_error(err: &TransactionError, program: Option<&Pubkey>) -> Failure {
+    let failure = |class, reason: String| Failure { class, reason };
+    match err {
+        TransactionError::InstructionError(index, InstructionError::Custom(code)) => {
+            match program.and_then(|p| program_error(p, *code)) {
+                Some((class, name)) => failure(class, name.to_string()),
+                None => match program {
+                    Some(program) => failure(
+                        FailureClass::Other,
+                        format!("error {code:#x} of {program} in instruction {index}"),
+                    ),
+                    None => failure(
+                        FailureClass::Other,
+                        format!("custom error {code:#x} in instruction {index}"),
+                    ),
+                },
+            }
+        }
+        TransactionError::InstructionError(_, InstructionError::ComputationalBudgetExceeded) => {
+            failure(
+                FailureClass::ComputeExhausted,
+                "compute budget exhausted".into(),
+            )
+        }
+        TransactionError::InstructionError(index, error) => failure(
+            FailureClass::Other,
+            format!("{error} in instruction {index}"),
+        ),
+        TransactionError::InsufficientFundsForFee => failure(
+            FailureClass::InsufficientFunds,
+            "insufficient SOL for the fee".into(),
+        ),
+        TransactionError::InsufficientFundsForRent { .. } => failure(
+            FailureClass::InsufficientFunds,
+            "insufficient SOL for rent".into(),
+        ),
+        TransactionError::BlockhashNotFound => {
+            failure(FailureClass::Expired, "blockhash expired".into())
+        }
+        err => failure(FailureClass::Other, err.to_string()),
+    }
+}
+
+// Custom errors of the programs trades go through
+fn program_error(program: &Pubkey, code: u32) -> Option<(FailureClass, &'static str)> {
+    let program = program.to_string();
+    let error = match (program.as_str(), code) {
+        (PUMPFUN_PROGRAM_ID, 6002) => (
+            FailureClass::Slippage,
+            "slippage exceeded, too much SOL required",
+        ),
+        (PUMPFUN_PROGRAM_ID, 6003) => (
+            FailureClass::Slippage,
+            "slippage exceeded, too little SOL received",
+        ),
+        (PUMPFUN_PROGRAM_ID, 6005) => (FailureClass::MarketChanged, "bonding curve complete"),
+        (JUPITER_PROGRAM_ID, 6000) => (FailureClass::Other, "empty route"),
+        (JUPITER_PROGRAM_ID, 6001) => (FailureClass::Slippage, "slippage exceeded"),
+        (RAYDIUM_AMM_PROGRAM_ID, 30) => (FailureClass::Slippage, "slippage exceeded"),
+        (_, 1) if program == spl_token::id().to_string() => (
+            FailureClass::InsufficientFunds,
+            "insufficient token balance",
+        ),
+        (_, 1) if program == solana_sdk::system_program::id().to_string() => {
+            (FailureClass::InsufficientFunds, "insufficient SOL")
+        }
+        _ => return None,
+    };
+    Some(error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slippage_messages() {
+        for message in [
+            "Program failed: custom program error: 0x1771",
+            "custom program error: 0x1772",
+            "custom program error: 0x1773",
+            "Slippage tolerance exceeded",
+        ] {
+            assert_eq!(FailureClass::of_message(message), FailureClass::Slippage);
+        }
+    }
+
+    #[test]
+    fn insufficient_messages() {
+        for message in [
+            "Transfer: insufficient lamports 100, need 200",
+            "Error: insufficient funds",
+            "Attempt to debit an account but found no record of a prior credit.",
+        ] {
+            assert_eq!(
+                FailureClass::of_message(message),
+                FailureClass::InsufficientFunds
+            );
+        }
+    }
+
+    #[test]
+    fn broadcast_errors() {
+        for message in [
+            "RPC response error -32002: Transaction simulation failed: Error processing Instruction 3: custom program error: 0x1772",
+            "Failed to simulate transaction",
+            "Unable to get latest blockhash: connection refused",
+        ] {
+            assert!(failed_before_broadcast(message), "{message}");
+        }
+        for message in [
+            "operation timed out",
+            "Unable to confirm transaction",
+            "error sending request for url (https://api.mainnet-beta.solana.com/)",
+        ] {
+            assert!(!failed_before_broadcast(message), "{message}");
+        }
+    }
+
+    #[test]
+    fn other_messages() {
+        assert_eq!(
+            FailureClass::of_message("Blockhash not found"),
+            FailureClass::Expired
+        );
+        assert_eq!(
+            FailureClass::of_message("custom program error: 0x1775"),
+            FailureClass::MarketChanged
+        );
+        assert_eq!(
+            FailureClass::of_message("Computational budget exceeded"),
+            FailureClass::ComputeExhausted
+        );
+        assert_eq!(
+            FailureClass::of_message("connection reset"),
+            FailureClass::Other
+        );
+    }
+}
@@ -0,0 +1,309 @@
+use std::{collections::HashMap, time::Duration};
+
+use crate::config::{DedupConfig, DedupPolicy};
+use crate::journal::{Journal, Side};
+
+struct PendingBuy {
+    user: String,
+    amount: f64,
+    submitted_at: i64,
+    // Only a safety net for buys that never settle, the retry policy bounds how long a buy runs
+    expires_at: i64,
+}
+
+// Decides whether a mint may be bought again, based on the journal and buys still in flight
+#[derive(Default)]
+pub struct BuyGuard {
+    pending: HashMap<String, PendingBuy>,
+}
+
+impl BuyGuard {
+    // Returns the reason a buy is skipped, or registers it as pending until it is settled
+    // or `hold` has passed
+    #[allow(clippy::too_many_arguments)]
+    pub fn check(
+        &mut self,
+        config: &DedupConfig,
+        journal: &Journal,
+        mint: &str,
+        user: &str,
+        amount: f64,
+        simulated: bool,
+        hold: Duration,
+    ) -> Result<(), String> {
+        let now = chrono::Utc::now().timestamp();
+        self.expire(now);
+
+        let pending = self.pending.get(mint);
+        let last_buy = journal
+            .last_trade(mint, Side::Buy, simulated)
+            .map(|r| r.timestamp)
+            .max(pending.map(|p| p.submitted_at));
+        let position = journal.position(mint, simulated);
+
+        match config.policy {
+            DedupPolicy::Always => {}
+            DedupPolicy::Once => {
+                if last_buy.is_some() {
+                    return Err("already bought".into());
+                }
+            }
+            DedupPolicy::Window => {
+                if let Some(last_buy) = last_buy {
+                    let elapsed = now - last_buy;
+                    if elapsed < config.window_secs as i64 {
+                        return Err(format!(
+                            "bought {elapsed}s ago, window is {}s",
+                            config.window_secs
+                        ));
+                    }
+                }
+            }
+            DedupPolicy::AfterClose => {
+                let open = position.as_ref().map(|p| p.tokens > 0.0).unwrap_or(false);
+                if open || pending.is_some() {
+                    return Err("position still open".into());
+                }
+            }
+            DedupPolicy::ScaleIn => {
+                let exposure = position.as_ref().map(|p| p.cost_basis).unwrap_or(0.0)
+                    + pending.map(|p| p.amount).unwrap_or(0.0);
+                if exposure + amount > config.max_exposure_sol {
+                    return Err(format!(
+                        "exposure {exposure:.4} SOL + {amount} SOL exceeds cap of {} SOL",
+                        config.max_exposure_sol
+                    ));
+                }
+            }
+        }
+
+        let expires_at = now + hold.as_secs() as i64;
+        let entry = self.pending.entry(mint.to_string()).or_insert(PendingBuy {
+            user: user.to_string(),
+            amount: 0.0,
+            submitted_at: now,
+            expires_at,
+        });
+        entry.user = user.to_string();
+        entry.amount += amount;
+        entry.submitted_at = now;
+        entry.expires_at = entry.expires_at.max(expires_at);
+
+        Ok(())
+    }
+
+    // Returns the reason `user` is still cooling down from their last buy
+    pub fn cooldown(
+        &self,
+        journal: &Journal,
+        user: &str,
+        cooldown_secs: u64,
+        simulated: bool,
+    ) -> Result<(), String> {
+        let now = chrono::Utc::now().timestamp();
+        let pending = self
+            .pending
+            .values()
+            .filter(|p| p.user.eq_ignore_ascii_case(user) && now < p.expires_at)
+            .map(|p| p.submitted_at)
+            .max();
+        let Some(last_buy) = journal.last_user_buy(user, simulated).max(pending) else {
+            return Ok(());
+        };
+        let elapsed = now - last_buy;
+        if elapsed < cooldown_secs as i64 {
+            return Err(format!(
+                "cooldown, last buy {elapsed}s ago (cooldown {cooldown_secs}s)"
+            ));
+        }
+        Ok(())
+    }
+
+    // Forget an in-flight buy once it has been journaled or has failed
+    pub fn settle(&mut self, mint: &str) {
+        self.pending.remove(mint);
+    }
+
+    fn expire(&mut self, now: i64) {
+        self.pending.retain(|_, pending| now < pending.expires_at);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::journal::TradeRecord;
+    use crate::market::Market;
+    use std::fs;
+
+    const HOLD: Duration = Duration::from_secs(300);
+
+    // A journal in a file of its own, removed when the test ends
+    struct TestJournal {
+        path: String,
+        journal: Journal,
+    }
+
+    impl TestJournal {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir()
+                .join(format!("dedup-{name}-{}.jsonl", std::process::id()))
+                .to_string_lossy()
+                .into_owned();
+            let _ = fs::remove_file(&path);
+            let journal = Journal::load_or_create(&path, "wallet");
+            Self { path, journal }
+        }
+
+        fn trade(&mut self, side: Side, sol_amount: f64, token_amount: f64, age_secs: i64) {
+            self.journal.append(TradeRecord {
+                signature: String::new(),
+                side,
+                mint: "mint".into(),
+                market: Market::PumpFun,
+                sol_amount,
+                token_amount,
+                tweet: String::new(),
+                user: "alice".into(),
+                timestamp: chrono::Utc::now().timestamp() - age_secs,
+                simulated: false,
+                estimated: false,
+                wallet: "wallet".into(),
+            });
+        }
+    }
+
+    impl Drop for TestJournal {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+
+    fn policy(policy: DedupPolicy) -> DedupConfig {
+        DedupConfig {
+            policy,
+            window_secs: 60,
+            max_exposure_sol: 1.0,
+        }
+    }
+
+    fn check(
+        guard: &mut BuyGuard,
+        config: &DedupConfig,
+        journal: &Journal,
+        amount: f64,
+    ) -> Result<(), String> {
+        guard.check(config, journal, "mint", "alice", amount, false, HOLD)
+    }
+
+    #[test]
+    fn always_buys() {
+        let mut test = TestJournal::new("always");
+        test.trade(Side::Buy, 0.1, 1000.0, 0);
+        let mut guard = BuyGuard::default();
+        let config = policy(DedupPolicy::Always);
+        assert!(check(&mut guard, &config, &test.journal, 0.1).is_ok());
+        assert!(check(&mut guard, &config, &test.journal, 0.1).is_ok());
+    }
+
+    #[test]
+    fn once_holds_until_settled() {
+        let test = TestJournal::new("once");
+        let mut guard = BuyGuard::default();
+        let config = policy(DedupPolicy::Once);
+        assert!(check(&mut guard, &config, &test.journal, 0.1).is_ok());
+        // Still in flight, however long it retries
+        assert!(check(&mut guard, &config, &test.journal, 0.1).is_err());
+        // A failed buy settles without a journal entry and may be tried again
+        guard.settle("mint");
+        assert!(check(&mut guard, &config, &test.journal, 0.1).is_ok());
+    }
+
+    #[test]
+    fn once_after_journaled() {
+        let mut test = TestJournal::new("journaled");
+        test.trade(Side::Buy, 0.1, 1000.0, 3600);
+        let mut guard = BuyGuard::default();
+        assert!(check(&mut guard, &policy(DedupPolicy::Once), &test.journal, 0.1).is_err());
+    }
+
+    #[test]
+    fn reservation_expires() {
+        let test = TestJournal::new("expire");
+        let mut guard = BuyGuard::default();
+        let config = policy(DedupPolicy::Once);
+        assert!(check(&mut guard, &config, &test.journal, 0.1).is_ok());
+        guard.expire(chrono::Utc::now().timestamp() + HOLD.as_secs() as i64 - 1);
+        assert!(check(&mut guard, &config, &test.journal, 0.1).is_err());
+        guard.expire(chrono::Utc::now().timestamp() + HOLD.as_secs() as i64 + 1);
+        assert!(check(&mut guard, &config, &test.journal, 0.1).is_ok());
+    }
+
+    #[test]
+    fn window() {
+        let config = policy(DedupPolicy::Window);
+        let mut recent = TestJournal::new("window-recent");
+        recent.trade(Side::Buy, 0.1, 1000.0, 30);
+        assert!(check(&mut BuyGuard::default(), &config, &recent.journal, 0.1).is_err());
+
+        let mut old = TestJournal::new("window-old");
+        old.trade(Side::Buy, 0.1, 1000.0, 120);
+        let mut guard = BuyGuard::default();
+        assert!(check(&mut guard, &config, &old.journal, 0.1).is_ok());
+        // The buy in flight restarts the window
+        assert!(check(&mut guard, &config, &old.journal, 0.1).is_err());
+    }
+
+    #[test]
+    fn after_close() {
+        let mut test = TestJournal::new("after-close");
+        let mut guard = BuyGuard::default();
+        let config = policy(DedupPolicy::AfterClose);
+        test.trade(Side::Buy, 0.1, 1000.0, 60);
+        assert!(check(&mut guard, &config, &test.journal, 0.1).is_err());
+        test.trade(Side::Sell, 0.2, 1000.0, 30);
+        assert!(check(&mut guard, &config, &test.journal, 0.1).is_ok());
+        // The new buy is in flight, so the position counts as open
+        assert!(check(&mut guard, &config, &test.journal, 0.1).is_err());
+    }
+
+    #[test]
+    fn scale_in() {
+        let mut test = TestJournal::new("scale-in");
+        let mut guard = BuyGuard::default();
+        let config = policy(DedupPolicy::ScaleIn);
+        test.trade(Side::Buy, 0.5, 1000.0, 60);
+        assert!(check(&mut guard, &config, &test.journal, 0.3).is_ok());
+        // 0.5 journaled and 0.3 in flight leave no room for another 0.3
+        assert!(check(&mut guard, &config, &test.journal, 0.3).is_err());
+        assert!(check(&mut guard, &config, &test.journal, 0.2).is_ok());
+    }
+
+    #[test]
+    fn cooldown() {
+        let mut test = TestJournal::new("cooldown");
+        let mut guard = BuyGuard::default();
+        assert!(guard.cooldown(&test.journal, "alice", 60, false).is_ok());
+
+        // A buy in flight starts the cooldown of its user only
+        guard
+            .check(
+                &policy(DedupPolicy::Always),
+                &test.journal,
+                "other",
+                "alice",
+                0.1,
+                false,
+                HOLD,
+            )
+            .unwrap();
+        assert!(guard.cooldown(&test.journal, "ALICE", 60, false).is_err());
+        assert!(guard.cooldown(&test.journal, "bob", 60, false).is_ok());
+        guard.settle("other");
+        assert!(guard.cooldown(&test.journal, "alice", 60, false).is_ok());
+
+        test.trade(Side::Buy, 0.1, 1000.0, 30);
+        assert!(guard.cooldown(&test.journal, "alice", 60, false).is_err());
+        assert!(guard.cooldown(&test.journal, "alice", 20, false).is_ok());
+    }
+}
@@ -0,0 +1,72 @@
+use std::{error::Error, num::NonZeroUsize, sync::Mutex, time::Duration};
+
+use futures::future::join_all;
+use lru::LruCache;
+use regex::Regex;
+use reqwest::{redirect::Policy, Client};
+use tokio::time::Instant;
+
+// Give up on a link rather than hold a trade back
+const EXPAND_TIMEOUT: Duration = Duration::from_secs(3);
+const MAX_REDIRECTS: usize = 5;
+const CACHE_SIZE: usize = 1024;
+
+// Expands shortened links with one pooled client, remembering the recent ones
+pub struct UrlExpander {
+    client: Client,
+    shortened: Regex,
+    cache: Mutex<LruCache<String, String>>,
+}
+
+impl UrlExpander {
+    pub fn new() -> Result<Self, reqwest::Error> {
+        let client = Client::builder()
+            .timeout(EXPAND_TIMEOUT)
+            .connect_timeout(EXPAND_TIMEOUT)
+            .redirect(Policy::limited(MAX_REDIRECTS))
+            .build()?;
+        Ok(Self {
+            client,
+            shortened: Regex::new(r"^https?://t\.co/[A-Za-z0-9]+").unwrap(),
+            cache: Mutex::new(LruCache::new(NonZeroUsize::new(CACHE_SIZE).unwrap())),
+        })
+    }
+
+    // Expand every link at once, links that fail to expand are kept as they are
+    pub async fn expand_all(&self, urls: &[String]) -> Vec<String> {
+        join_all(urls.iter().map(|url| async move {
+            if !self.shortened.is_match(url) {
+                return url.clone();
+            }
+            match self.expand(url).await {
+                Ok(expanded) => expanded,
+                Err(e) => {
+                    tracing::error!("Failed to expand {url}: {e}");
+                    url.clone()
+                }
+            }
+        }))
+        .await
+    }
+
+    async fn expand(&self, url: &str) -> Result<String, Box<dyn Error + Send + Sync>> {
+        if let Some(expanded) = self.cache.lock().unwrap().get(url) {
+            tracing::info!("Expanded {url} to {expanded} from cache");
+            return Ok(expanded.clone());
+        }
+
+        let start = Instant::now();
+        let response = self.client.get(url).send().await?.error_for_status()?;
+        let expanded = response.url().to_string();
+        tracing::info!(
+            "Expanded {url} to {expanded} in {}ms",
+            start.elapsed().as_millis()
+        );
+
+        self.cache
+            .lock()
+            .unwrap()
+            .put(url.to_string(), expanded.clone());
+        Ok(expanded)
+    }
+}
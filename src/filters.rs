@@ -0,0 +1,146 @@
+use chrono::{Local, NaiveTime};
+
+use crate::{
+    config::{ActiveHours, FilterConfig},
+    signals::{Signal, TweetKind},
+};
+
+// Returns the rule that rejects the signal, if any. The cooldown is checked later
+// in the buy path since it depends on trades, not on the tweet.
+pub fn check(rules: &FilterConfig, signal: &Signal) -> Result<(), String> {
+    match signal.kind {
+        TweetKind::Reply if rules.ignore_replies => return Err("replies ignored".into()),
+        TweetKind::Retweet if rules.ignore_retweets => return Err("retweets ignored".into()),
+        TweetKind::Quote if rules.ignore_quotes => return Err("quotes ignored".into()),
+        _ => {}
+    }
+
+    if let Some(max_age) = rules.max_tweet_age_secs {
+        let age = chrono::Utc::now().timestamp() - signal.timestamp;
+        if age > max_age as i64 {
+            return Err(format!("tweet {age}s old (max {max_age}s)"));
+        }
+    }
+
+    if let Some(hours) = &rules.active_hours {
+        if !in_active_hours(hours, Local::now().time())? {
+            return Err(format!(
+                "outside active hours {}-{}",
+                hours.start, hours.end
+            ));
+        }
+    }
+
+    let text = searchable_text(signal);
+    if let Some(keyword) = rules
+        .ignore_keywords
+        .iter()
+        .find(|k| text.contains(&k.to_lowercase()))
+    {
+        return Err(format!("ignored keyword \"{keyword}\""));
+    }
+    if !rules.require_keywords.is_empty()
+        && !rules
+            .require_keywords
+            .iter()
+            .any(|k| text.contains(&k.to_lowercase()))
+    {
+        return Err("no required keyword".into());
+    }
+
+    if let Some(cashtag) = &rules.required_cashtag {
+        let cashtag = cashtag.trim_start_matches('$');
+        let tagged = signal
+            .cashtags
+            .iter()
+            .any(|c| c.eq_ignore_ascii_case(cashtag))
+            || text.contains(&format!("${}", cashtag.to_lowercase()));
+        if !tagged {
+            return Err(format!("missing cashtag ${cashtag}"));
+        }
+    }
+
+    Ok(())
+}
+
+// Lowercased tweet and quoted tweet text
+fn searchable_text(signal: &Signal) -> String {
+    match &signal.quoted_text {
+        Some(quoted) => format!("{}\n{}", signal.text, quoted).to_lowercase(),
+        None => signal.text.to_lowercase(),
+    }
+}
+
+// Hours are validated when the config loads
+fn in_active_hours(hours: &ActiveHours, now: NaiveTime) -> Result<bool, String> {
+    let (start, end) = hours.bounds()?;
+
+    Ok(if start <= end {
+        start <= now && now < end
+    } else {
+        now >= start || now < end
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(time: &str) -> NaiveTime {
+        NaiveTime::parse_from_str(time, "%H:%M").unwrap()
+    }
+
+    fn hours(start: &str, end: &str) -> ActiveHours {
+        ActiveHours {
+            start: start.into(),
+            end: end.into(),
+        }
+    }
+
+    fn signal(text: &str) -> Signal {
+        Signal::new("test", "1".into(), "alice".into(), text.into())
+    }
+
+    #[test]
+    fn daytime_hours() {
+        let hours = hours("09:00", "17:00");
+        assert!(in_active_hours(&hours, at("09:00")).unwrap());
+        assert!(in_active_hours(&hours, at("16:59")).unwrap());
+        assert!(!in_active_hours(&hours, at("17:00")).unwrap());
+        assert!(!in_active_hours(&hours, at("08:59")).unwrap());
+    }
+
+    #[test]
+    fn hours_past_midnight() {
+        let hours = hours("22:00", "06:00");
+        assert!(in_active_hours(&hours, at("22:00")).unwrap());
+        assert!(in_active_hours(&hours, at("23:59")).unwrap());
+        assert!(in_active_hours(&hours, at("00:00")).unwrap());
+        assert!(in_active_hours(&hours, at("05:59")).unwrap());
+        assert!(!in_active_hours(&hours, at("06:00")).unwrap());
+        assert!(!in_active_hours(&hours, at("12:00")).unwrap());
+        assert!(!in_active_hours(&hours, at("21:59")).unwrap());
+    }
+
+    #[test]
+    fn keywords() {
+        let rules = FilterConfig {
+            require_keywords: vec!["Launch".into(), "CA".into()],
+            ignore_keywords: vec!["giveaway".into()],
+            ..Default::default()
+        };
+        assert!(check(&rules, &signal("We LAUNCH today")).is_ok());
+        assert!(check(&rules, &signal("ca: soon")).is_ok());
+        assert!(check(&rules, &signal("gm")).is_err());
+        // Ignored keywords win over required ones
+        assert!(check(&rules, &signal("Launch GIVEAWAY")).is_err());
+
+        // The quoted tweet is searched as well
+        let mut quote = signal("look at this");
+        quote.kind = TweetKind::Quote;
+        quote.quoted_text = Some("launch in 5".into());
+        assert!(check(&rules, &quote).is_ok());
+        quote.quoted_text = Some("giveaway, launch in 5".into());
+        assert!(check(&rules, &quote).is_err());
+    }
+}
@@ -0,0 +1,545 @@
+use serde::Deserialize;
+use serde_json::Value;
+use std::error::Error;
+
+use crate::signals::TweetKind;
+
+// Deserializes to `Unknown` instead of failing when the payload does not match `T`
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum Tolerant<T> {
+    Known(T),
+    Unknown(Value),
+}
+
+#[derive(Deserialize, Debug)]
+struct Response<T> {
+    data: T,
+}
+
+#[derive(Deserialize, Debug)]
+struct HomeData {
+    home: Home,
+}
+
+#[derive(Deserialize, Debug)]
+struct Home {
+    home_timeline_urt: Timeline,
+}
+
+#[derive(Deserialize, Debug)]
+struct UserData {
+    #[serde(default)]
+    user: Option<UserResults>,
+}
+
+#[derive(Deserialize, Debug)]
+struct UserResults {
+    #[serde(default)]
+    result: Option<UserResult>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "__typename")]
+enum UserResult {
+    User(User),
+    UserUnavailable {
+        #[serde(default)]
+        reason: Option<String>,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize, Debug)]
+struct User {
+    rest_id: String,
+    // Following uses `timeline`, tweets and replies use `timeline_v2`
+    #[serde(default, alias = "timeline_v2")]
+    timeline: Option<TimelineWrapper>,
+}
+
+#[derive(Deserialize, Debug)]
+struct TimelineWrapper {
+    timeline: Timeline,
+}
+
+#[derive(Deserialize, Debug)]
+struct Timeline {
+    #[serde(default)]
+    instructions: Vec<Instruction>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Instruction {
+    #[serde(default)]
+    entries: Vec<Tolerant<Entry>>,
+    // TimelinePinEntry carries a single entry
+    #[serde(default)]
+    entry: Option<Tolerant<Entry>>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Entry {
+    #[serde(rename = "entryId")]
+    entry_id: String,
+    content: EntryContent,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "entryType")]
+enum EntryContent {
+    TimelineTimelineItem {
+        #[serde(rename = "itemContent")]
+        item_content: Tolerant<ItemContent>,
+    },
+    TimelineTimelineModule {
+        #[serde(default)]
+        items: Vec<ModuleItem>,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize, Debug)]
+struct ModuleItem {
+    item: ModuleItemContent,
+}
+
+#[derive(Deserialize, Debug)]
+struct ModuleItemContent {
+    #[serde(rename = "itemContent")]
+    item_content: Tolerant<ItemContent>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "itemType")]
+enum ItemContent {
+    TimelineTweet {
+        tweet_results: TweetResults,
+        #[serde(default, rename = "promotedMetadata")]
+        promoted_metadata: Option<Value>,
+    },
+    TimelineUser {
+        user_results: UserResults,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize, Debug)]
+struct TweetResults {
+    #[serde(default)]
+    result: Option<TweetResult>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "__typename")]
+enum TweetResult {
+    Tweet(Box<Tweet>),
+    // Tweets from accounts with limited visibility are wrapped once more
+    TweetWithVisibilityResults {
+        tweet: Box<Tweet>,
+    },
+    TweetTombstone,
+    TweetUnavailable,
+    #[serde(other)]
+    Other,
+}
+
+impl TweetResults {
+    fn into_tweet(self) -> Option<Box<Tweet>> {
+        match self.result? {
+            TweetResult::Tweet(tweet) => Some(tweet),
+            TweetResult::TweetWithVisibilityResults { tweet } => Some(tweet),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct Tweet {
+    legacy: TweetLegacy,
+    #[serde(default)]
+    quoted_status_result: Option<TweetResults>,
+}
+
+#[derive(Deserialize, Debug)]
+struct TweetLegacy {
+    id_str: String,
+    user_id_str: String,
+    full_text: String,
+    // e.g. "Thu Jun 13 18:02:11 +0000 2024"
+    #[serde(default)]
+    created_at: Option<String>,
+    #[serde(default)]
+    entities: Entities,
+    #[serde(default)]
+    in_reply_to_status_id_str: Option<String>,
+    #[serde(default)]
+    is_quote_status: bool,
+    #[serde(default)]
+    retweeted_status_result: Option<TweetResults>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct Entities {
+    #[serde(default)]
+    urls: Vec<UrlEntity>,
+    #[serde(default)]
+    symbols: Vec<SymbolEntity>,
+}
+
+#[derive(Deserialize, Debug)]
+struct UrlEntity {
+    url: String,
+    #[serde(default)]
+    expanded_url: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct SymbolEntity {
+    text: String,
+}
+
+// A tweet as read from a timeline
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimelineTweet {
+    pub id: String,
+    pub user_id: String,
+    pub text: String,
+    // Unix seconds
+    pub created_at: Option<i64>,
+    pub kind: TweetKind,
+    pub quoted_text: Option<String>,
+    // Expanded links, the t.co link when Twitter did not expand it
+    pub urls: Vec<String>,
+    // Cashtags without the `$`
+    pub cashtags: Vec<String>,
+}
+
+impl From<Tweet> for TimelineTweet {
+    fn from(tweet: Tweet) -> Self {
+        let legacy = tweet.legacy;
+        let retweeted = legacy
+            .retweeted_status_result
+            .and_then(TweetResults::into_tweet);
+        let quoted = tweet
+            .quoted_status_result
+            .and_then(TweetResults::into_tweet);
+
+        let kind = if retweeted.is_some() {
+            TweetKind::Retweet
+        } else if legacy.is_quote_status {
+            TweetKind::Quote
+        } else if legacy.in_reply_to_status_id_str.is_some() {
+            TweetKind::Reply
+        } else {
+            TweetKind::Original
+        };
+
+        // The text of a retweet is truncated, its links are only complete on the original
+        let mut entities = vec![legacy.entities];
+        if let Some(retweeted) = retweeted {
+            entities.push(retweeted.legacy.entities);
+        }
+        let mut urls: Vec<String> = vec![];
+        let mut cashtags: Vec<String> = vec![];
+        for entities in entities {
+            for url in entities.urls {
+                let url = url.expanded_url.unwrap_or(url.url);
+                if !urls.contains(&url) {
+                    urls.push(url);
+                }
+            }
+            for symbol in entities.symbols {
+                if !cashtags.contains(&symbol.text) {
+                    cashtags.push(symbol.text);
+                }
+            }
+        }
+
+        let created_at = legacy.created_at.and_then(|created_at| {
+            chrono::DateTime::parse_from_str(&created_at, "%a %b %d %H:%M:%S %z %Y")
+                .ok()
+                .map(|t| t.timestamp())
+        });
+
+        Self {
+            id: legacy.id_str,
+            user_id: legacy.user_id_str,
+            text: legacy.full_text,
+            created_at,
+            kind,
+            quoted_text: quoted.map(|q| q.legacy.full_text),
+            urls,
+            cashtags,
+        }
+    }
+}
+
+// Tweets of the HomeLatestTimeline query, promoted tweets are left out
+pub fn parse_home_timeline(body: &str) -> Result<Vec<TimelineTweet>, Box<dyn Error + Send + Sync>> {
+    let response: Response<HomeData> = serde_json::from_str(body)?;
+    Ok(timeline_tweets(response.data.home.home_timeline_urt))
+}
+
+// Tweets of the UserTweets and UserTweetsAndReplies queries
+pub fn parse_user_timeline(body: &str) -> Result<Vec<TimelineTweet>, Box<dyn Error + Send + Sync>> {
+    let user = parse_user(body)?;
+    let timeline = user.timeline.ok_or("Response has no timeline")?;
+    Ok(timeline_tweets(timeline.timeline))
+}
+
+// Ids of the accounts listed by the Following query
+pub fn parse_following(body: &str) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+    let user = parse_user(body)?;
+    let timeline = user.timeline.ok_or("Response has no timeline")?;
+
+    let mut ids = vec![];
+    for entry in timeline_entries(timeline.timeline) {
+        for item in entry_items(entry) {
+            match item {
+                ItemContent::TimelineUser {
+                    user_results:
+                        UserResults {
+                            result: Some(UserResult::User(user)),
+                        },
+                } => ids.push(user.rest_id),
+                ItemContent::TimelineUser { .. } => {
+                    tracing::warn!("Skipping unavailable user in following list");
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(ids)
+}
+
+// Rest id of the UserByScreenName query
+pub fn parse_user_id(body: &str) -> Result<String, Box<dyn Error + Send + Sync>> {
+    Ok(parse_user(body)?.rest_id)
+}
+
+fn parse_user(body: &str) -> Result<User, Box<dyn Error + Send + Sync>> {
+    let response: Response<UserData> = serde_json::from_str(body)?;
+    match response.data.user.and_then(|u| u.result) {
+        Some(UserResult::User(user)) => Ok(user),
+        Some(UserResult::UserUnavailable { reason }) => Err(format!(
+            "User is unavailable: {}",
+            reason.unwrap_or_else(|| "no reason given".into())
+        )
+        .into()),
+        Some(UserResult::Other) => Err("Unknown user result type".into()),
+        None => Err("User not found".into()),
+    }
+}
+
+fn timeline_entries(timeline: Timeline) -> Vec<Entry> {
+    let mut entries = vec![];
+    for instruction in timeline.instructions {
+        for entry in instruction.entries.into_iter().chain(instruction.entry) {
+            match entry {
+                Tolerant::Known(entry) => entries.push(entry),
+                Tolerant::Unknown(value) => {
+                    tracing::warn!("Skipping unknown timeline entry {}", value["entryId"]);
+                }
+            }
+        }
+    }
+    entries
+}
+
+// Item contents of an entry, conversation modules hold several
+fn entry_items(entry: Entry) -> Vec<ItemContent> {
+    let contents = match entry.content {
+        EntryContent::TimelineTimelineItem { item_content } => vec![item_content],
+        EntryContent::TimelineTimelineModule { items } => {
+            items.into_iter().map(|i| i.item.item_content).collect()
+        }
+        EntryContent::Other => vec![],
+    };
+
+    contents
+        .into_iter()
+        .filter_map(|content| match content {
+            Tolerant::Known(content) => Some(content),
+            Tolerant::Unknown(value) => {
+                tracing::warn!(
+                    "Skipping unknown item in timeline entry {}: {}",
+                    entry.entry_id,
+                    value["itemType"]
+                );
+                None
+            }
+        })
+        .collect()
+}
+
+fn timeline_tweets(timeline: Timeline) -> Vec<TimelineTweet> {
+    let mut tweets = vec![];
+    for entry in timeline_entries(timeline) {
+        let entry_id = entry.entry_id.clone();
+        for item in entry_items(entry) {
+            let ItemContent::TimelineTweet {
+                tweet_results,
+                promoted_metadata,
+            } = item
+            else {
+                continue;
+            };
+            if promoted_metadata.is_some() || entry_id.starts_with("promoted") {
+                continue;
+            }
+            let tweet = match tweet_results.result {
+                Some(TweetResult::Tweet(tweet)) => tweet,
+                Some(TweetResult::TweetWithVisibilityResults { tweet }) => tweet,
+                Some(TweetResult::TweetTombstone) | Some(TweetResult::TweetUnavailable) => {
+                    continue;
+                }
+                Some(TweetResult::Other) | None => {
+                    tracing::warn!("Skipping unknown tweet result in timeline entry {entry_id}");
+                    continue;
+                }
+            };
+            tweets.push(TimelineTweet::from(*tweet));
+        }
+    }
+    tweets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOME_TIMELINE: &str = include_str!("../fixtures/twitter/home_latest_timeline.json");
+    const FOLLOWING: &str = include_str!("../fixtures/twitter/following.json");
+    const USER_BY_SCREEN_NAME: &str = include_str!("../fixtures/twitter/user_by_screen_name.json");
+    const USER_UNAVAILABLE: &str = include_str!("../fixtures/twitter/user_unavailable.json");
+    const USER_TWEETS: &str = include_str!("../fixtures/twitter/user_tweets_and_replies.json");
+
+    #[test]
+    fn home_timeline_skips_promoted_tombstones_and_unknown_entries() {
+        let tweets = parse_home_timeline(HOME_TIMELINE).unwrap();
+        let ids: Vec<&str> = tweets.iter().map(|t| t.id.as_str()).collect();
+        assert_eq!(
+            ids,
+            [
+                "1801234567890123456",
+                "1801234567890123457",
+                "1801234567890123459",
+                "1801234567890123460",
+                "1801234567890123463",
+                "1801234567890123464"
+            ]
+        );
+        assert_eq!(tweets[0].user_id, "1790897799313154048");
+        assert!(tweets[0].text.contains("https://t.co/AbCdEf1234"));
+    }
+
+    #[test]
+    fn home_timeline_unwraps_visibility_results() {
+        let tweets = parse_home_timeline(HOME_TIMELINE).unwrap();
+        let tweet = tweets
+            .iter()
+            .find(|t| t.id == "1801234567890123457")
+            .unwrap();
+        assert_eq!(tweet.user_id, "44196397");
+        assert_eq!(tweet.text, "limited visibility tweet");
+    }
+
+    #[test]
+    fn home_timeline_reads_metadata() {
+        let tweets = parse_home_timeline(HOME_TIMELINE).unwrap();
+        let tweet = |id: &str| tweets.iter().find(|t| t.id == id).unwrap();
+
+        let original = tweet("1801234567890123456");
+        assert_eq!(original.kind, TweetKind::Original);
+        assert_eq!(original.created_at, Some(1718301731));
+        assert_eq!(
+            original.urls,
+            ["https://pump.fun/7GCihgDB8fe6KNjn2MYtkzZcRjQy3t9GHdC8uHYmW2hr"]
+        );
+        assert_eq!(original.cashtags, ["GEM"]);
+
+        assert_eq!(tweet("1801234567890123460").kind, TweetKind::Reply);
+
+        let retweet = tweet("1801234567890123463");
+        assert_eq!(retweet.kind, TweetKind::Retweet);
+        assert_eq!(
+            retweet.urls,
+            ["https://dexscreener.com/solana/8sLbNZoA1cfnvMJLPfp98ZLAnFSYCFApfJKMbiXNLwxj"]
+        );
+        assert_eq!(retweet.cashtags, ["MOON"]);
+
+        let quote = tweet("1801234567890123464");
+        assert_eq!(quote.kind, TweetKind::Quote);
+        assert_eq!(
+            quote.quoted_text.as_deref(),
+            Some("CA in the link $MOON https://t.co/ZzYyXx9876")
+        );
+        assert!(quote.urls.is_empty());
+    }
+
+    #[test]
+    fn home_timeline_rejects_unrelated_payload() {
+        assert!(parse_home_timeline(r#"{"errors":[{"message":"Bad guest token"}]}"#).is_err());
+    }
+
+    #[test]
+    fn following_lists_every_user() {
+        let ids = parse_following(FOLLOWING).unwrap();
+        assert_eq!(ids, ["1790897799313154048", "44196397"]);
+    }
+
+    #[test]
+    fn user_id_by_screen_name() {
+        assert_eq!(
+            parse_user_id(USER_BY_SCREEN_NAME).unwrap(),
+            "1790897799313154048"
+        );
+    }
+
+    #[test]
+    fn unavailable_and_missing_users_are_errors() {
+        let err = parse_user_id(USER_UNAVAILABLE).unwrap_err();
+        assert!(err.to_string().contains("Suspended"));
+        assert!(parse_user_id(r#"{"data":{}}"#).is_err());
+    }
+
+    // Scrubbed real responses, see fixtures/twitter/README.md
+    #[test]
+    fn captured_responses_parse() {
+        let dir =
+            std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/twitter/captured");
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            if !name.ends_with(".json") {
+                continue;
+            }
+            let body = std::fs::read_to_string(&path).unwrap();
+            let parsed = if name.starts_with("home_latest_timeline") {
+                parse_home_timeline(&body).map(drop)
+            } else if name.starts_with("user_tweets") {
+                parse_user_timeline(&body).map(drop)
+            } else if name.starts_with("following") {
+                parse_following(&body).map(drop)
+            } else if name.starts_with("user_by_screen_name") {
+                parse_user_id(&body).map(drop)
+            } else {
+                panic!("{name}: no parser for this operation");
+            };
+            if let Err(e) = parsed {
+                panic!("{name}: {e}");
+            }
+        }
+    }
+
+    #[test]
+    fn user_timeline_includes_pinned_tweet() {
+        let tweets = parse_user_timeline(USER_TWEETS).unwrap();
+        let ids: Vec<&str> = tweets.iter().map(|t| t.id.as_str()).collect();
+        assert_eq!(ids, ["1801234567890123470", "1801234567890123471"]);
+    }
+}
@@ -0,0 +1,287 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+
+use crate::market::Market;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+// A single fill, either SOL spent/tokens received (buy) or tokens sold/SOL received (sell)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TradeRecord {
+    pub signature: String,
+    pub side: Side,
+    pub mint: String,
+    pub market: Market,
+    pub sol_amount: f64,
+    pub token_amount: f64,
+    pub tweet: String,
+    pub user: String,
+    pub timestamp: i64,
+    #[serde(default)]
+    pub simulated: bool,
+    // Amounts quoted when the trade landed, its transaction could not be read
+    #[serde(default)]
+    pub estimated: bool,
+    // Public key of the wallet that traded, older entries belong to the default wallet
+    #[serde(default)]
+    pub wallet: String,
+}
+
+// Running totals for one mint, using average cost for the cost basis
+#[derive(Debug, Clone, Default)]
+pub struct PositionBook {
+    pub mint: String,
+    pub wallet: String,
+    pub market: Market,
+    pub user: String,
+    pub tokens: f64,
+    pub cost_basis: f64,
+    pub realized_pnl: f64,
+}
+
+impl PositionBook {
+    fn apply(&mut self, record: &TradeRecord) {
+        self.market = record.market;
+        self.wallet = record.wallet.clone();
+        match record.side {
+            Side::Buy => {
+                self.user = record.user.clone();
+                self.tokens += record.token_amount;
+                self.cost_basis += record.sol_amount;
+            }
+            Side::Sell => {
+                let sold = record.token_amount.min(self.tokens);
+                let released = if self.tokens > 0.0 {
+                    self.cost_basis * (sold / self.tokens)
+                } else {
+                    0.0
+                };
+                self.realized_pnl += record.sol_amount - released;
+                self.tokens -= sold;
+                self.cost_basis -= released;
+            }
+        }
+    }
+}
+
+pub struct Journal {
+    path: String,
+    records: Vec<TradeRecord>,
+}
+
+impl Journal {
+    // Loads the journal from a JSON lines file, one trade per line
+    pub fn load_or_create(path: &str, default_wallet: &str) -> Self {
+        let mut records: Vec<TradeRecord> = fs::read_to_string(path)
+            .map(|data| {
+                data.lines()
+                    .filter(|line| !line.trim().is_empty())
+                    .filter_map(|line| match serde_json::from_str(line) {
+                        Ok(record) => Some(record),
+                        Err(e) => {
+                            tracing::error!("Skipping invalid journal entry: {e}");
+                            None
+                        }
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        for record in records.iter_mut().filter(|r| r.wallet.is_empty()) {
+            record.wallet = default_wallet.to_string();
+        }
+
+        Self {
+            path: path.to_string(),
+            records,
+        }
+    }
+
+    pub fn append(&mut self, record: TradeRecord) {
+        let line = match serde_json::to_string(&record) {
+            Ok(line) => line,
+            Err(e) => {
+                tracing::error!("Unable to serialize trade record: {e}");
+                return;
+            }
+        };
+        let written = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| writeln!(file, "{line}"));
+        if let Err(e) = written {
+            tracing::error!("Unable to write trade journal: {e}");
+        }
+        self.records.push(record);
+    }
+
+    pub fn last_trade(&self, mint: &str, side: Side, simulated: bool) -> Option<&TradeRecord> {
+        self.records
+            .iter()
+            .rev()
+            .find(|r| r.mint == mint && r.side == side && r.simulated == simulated)
+    }
+
+    // Time of the last buy triggered by `user`
+    pub fn last_user_buy(&self, user: &str, simulated: bool) -> Option<i64> {
+        self.records
+            .iter()
+            .rev()
+            .find(|r| {
+                r.side == Side::Buy && r.simulated == simulated && r.user.eq_ignore_ascii_case(user)
+            })
+            .map(|r| r.timestamp)
+    }
+
+    // Position in `mint` over all wallets
+    pub fn position(&self, mint: &str, simulated: bool) -> Option<PositionBook> {
+        self.positions(simulated).remove(mint)
+    }
+
+    // Positions by mint over all wallets
+    pub fn positions(&self, simulated: bool) -> HashMap<String, PositionBook> {
+        Self::books(self.records.iter().filter(|r| r.simulated == simulated))
+    }
+
+    pub fn wallet_position(
+        &self,
+        wallet: &str,
+        mint: &str,
+        simulated: bool,
+    ) -> Option<PositionBook> {
+        self.wallet_positions(wallet, simulated).remove(mint)
+    }
+
+    // Positions by mint of one wallet
+    pub fn wallet_positions(&self, wallet: &str, simulated: bool) -> HashMap<String, PositionBook> {
+        Self::books(
+            self.records
+                .iter()
+                .filter(|r| r.simulated == simulated && r.wallet == wallet),
+        )
+    }
+
+    pub fn open_positions(&self, wallet: &str, simulated: bool) -> Vec<PositionBook> {
+        self.wallet_positions(wallet, simulated)
+            .into_values()
+            .filter(|p| p.tokens > 0.0)
+            .collect()
+    }
+
+    pub fn realized_pnl(&self, wallet: &str, simulated: bool) -> f64 {
+        self.wallet_positions(wallet, simulated)
+            .values()
+            .map(|p| p.realized_pnl)
+            .sum()
+    }
+
+    fn books<'a>(records: impl Iterator<Item = &'a TradeRecord>) -> HashMap<String, PositionBook> {
+        let mut books: HashMap<String, PositionBook> = HashMap::new();
+        for record in records {
+            books
+                .entry(record.mint.clone())
+                .or_insert_with(|| PositionBook {
+                    mint: record.mint.clone(),
+                    ..Default::default()
+                })
+                .apply(record);
+        }
+        books
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(side: Side, sol_amount: f64, token_amount: f64) -> TradeRecord {
+        TradeRecord {
+            signature: String::new(),
+            side,
+            mint: "mint".into(),
+            market: Market::PumpFun,
+            sol_amount,
+            token_amount,
+            tweet: String::new(),
+            user: "alice".into(),
+            timestamp: 0,
+            simulated: false,
+            estimated: false,
+            wallet: "wallet".into(),
+        }
+    }
+
+    fn book(records: &[TradeRecord]) -> PositionBook {
+        let mut book = PositionBook::default();
+        for record in records {
+            book.apply(record);
+        }
+        book
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{actual} is not {expected}"
+        );
+    }
+
+    #[test]
+    fn average_cost() {
+        let book = book(&[
+            record(Side::Buy, 1.0, 1000.0),
+            record(Side::Buy, 3.0, 1000.0),
+        ]);
+        assert_close(book.tokens, 2000.0);
+        assert_close(book.cost_basis, 4.0);
+        assert_close(book.realized_pnl, 0.0);
+        assert_eq!(book.user, "alice");
+    }
+
+    #[test]
+    fn partial_sells() {
+        // 2000 tokens at 0.002 SOL each
+        let mut records = vec![
+            record(Side::Buy, 1.0, 1000.0),
+            record(Side::Buy, 3.0, 1000.0),
+            record(Side::Sell, 3.0, 500.0),
+        ];
+        let first = book(&records);
+        assert_close(first.tokens, 1500.0);
+        assert_close(first.cost_basis, 3.0);
+        assert_close(first.realized_pnl, 2.0);
+
+        // Selling at a loss reduces the realized PnL
+        records.push(record(Side::Sell, 0.5, 500.0));
+        let second = book(&records);
+        assert_close(second.tokens, 1000.0);
+        assert_close(second.cost_basis, 2.0);
+        assert_close(second.realized_pnl, 1.5);
+
+        // A later buy is averaged into what is left
+        records.push(record(Side::Buy, 1.0, 1000.0));
+        let third = book(&records);
+        assert_close(third.tokens, 2000.0);
+        assert_close(third.cost_basis, 3.0);
+        assert_close(third.realized_pnl, 1.5);
+    }
+
+    #[test]
+    fn oversold() {
+        // Sells beyond the journaled tokens, e.g. of tokens bought elsewhere, only close the book
+        let book = book(&[
+            record(Side::Buy, 1.0, 1000.0),
+            record(Side::Sell, 3.0, 1500.0),
+        ]);
+        assert_close(book.tokens, 0.0);
+        assert_close(book.cost_basis, 0.0);
+        assert_close(book.realized_pnl, 2.0);
+    }
+}
@@ -0,0 +1,232 @@
+use std::{error::Error, fmt, str::FromStr};
+
+use bip39::{Language, Mnemonic};
+use solana_sdk::{
+    bs58,
+    derivation_path::DerivationPath,
+    signature::Keypair,
+    signer::{
+        keypair::{
+            generate_seed_from_seed_phrase_and_passphrase, keypair_from_seed,
+            keypair_from_seed_and_derivation_path,
+        },
+        Signer,
+    },
+};
+use zeroize::Zeroizing;
+
+// Path Phantom and Solflare derive their first account from
+pub const DEFAULT_DERIVATION_PATH: &str = "m/44'/501'/0'/0'";
+
+// A seed phrase and the path the wallet was derived with, kept to export it again
+pub struct SeedPhrase {
+    pub phrase: Zeroizing<String>,
+    pub derivation_path: String,
+}
+
+pub struct ImportedKey {
+    pub keypair: Keypair,
+    pub seed: Option<SeedPhrase>,
+}
+
+// Read a key file in any supported format: raw 64 bytes, a Solana CLI JSON array,
+// a base58 private key or a BIP39 seed phrase derived along `derivation_path`
+pub fn import(data: &[u8], derivation_path: &str) -> Result<ImportedKey, Box<dyn Error>> {
+    let text = match std::str::from_utf8(data) {
+        Ok(text) => Zeroizing::new(text.trim().to_string()),
+        Err(_) => Zeroizing::new(String::new()),
+    };
+
+    if text.starts_with('[') {
+        let bytes: Zeroizing<Vec<u8>> = Zeroizing::new(serde_json::from_str(&text)?);
+        return Ok(ImportedKey {
+            keypair: keypair_from_bytes(&bytes)?,
+            seed: None,
+        });
+    }
+
+    // A mnemonic export carries its derivation path on the line after the phrase
+    let (words, derivation_path) = match text.rsplit_once('\n') {
+        Some((words, path)) if path.trim().starts_with("m/") => (words, path.trim()),
+        _ => (text.as_str(), derivation_path),
+    };
+    if words.split_whitespace().count() >= 12 {
+        let phrase = Zeroizing::new(words.split_whitespace().collect::<Vec<_>>().join(" "));
+        Mnemonic::validate(&phrase, Language::English)
+            .map_err(|e| format!("Invalid seed phrase: {e}"))?;
+        let path = DerivationPath::from_absolute_path_str(derivation_path)
+            .map_err(|e| format!("Invalid derivation path {derivation_path}: {e}"))?;
+        let seed = Zeroizing::new(generate_seed_from_seed_phrase_and_passphrase(&phrase, ""));
+        return Ok(ImportedKey {
+            keypair: keypair_from_seed_and_derivation_path(&seed, Some(path))?,
+            seed: Some(SeedPhrase {
+                phrase,
+                derivation_path: derivation_path.to_string(),
+            }),
+        });
+    }
+
+    // The format this bot wrote before, checked after the text formats it cannot be confused with
+    if data.len() == 64 {
+        return Ok(ImportedKey {
+            keypair: keypair_from_bytes(data)?,
+            seed: None,
+        });
+    }
+
+    let bytes = Zeroizing::new(
+        bs58::decode(text.as_str())
+            .into_vec()
+            .map_err(|_| "Key file is not a JSON array, base58 key or seed phrase")?,
+    );
+    Ok(ImportedKey {
+        keypair: keypair_from_bytes(&bytes)?,
+        seed: None,
+    })
+}
+
+// Keypair::from_bytes trusts the public half, a mismatch means a corrupt or mistyped key
+fn keypair_from_bytes(bytes: &[u8]) -> Result<Keypair, Box<dyn Error>> {
+    let keypair = Keypair::from_bytes(bytes)?;
+    if keypair_from_seed(&bytes[..32])?.pubkey() != keypair.pubkey() {
+        return Err("Secret and public key of the key file do not match".into());
+    }
+    Ok(keypair)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    // `[12,34,...]` as written by solana-keygen
+    SolanaCli,
+    // Private key string as shown by Phantom and Solflare
+    Base58,
+    // Only for wallets imported from a seed phrase
+    Mnemonic,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "solana-cli" | "json" => Ok(ExportFormat::SolanaCli),
+            "base58" => Ok(ExportFormat::Base58),
+            "mnemonic" | "seed" => Ok(ExportFormat::Mnemonic),
+            _ => Err(format!(
+                "Unknown export format {s}, use solana-cli, base58 or mnemonic"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ExportFormat::SolanaCli => "solana-cli",
+            ExportFormat::Base58 => "base58",
+            ExportFormat::Mnemonic => "mnemonic",
+        };
+        write!(f, "{name}")
+    }
+}
+
+pub fn export(
+    format: ExportFormat,
+    keypair: &Keypair,
+    seed: Option<&SeedPhrase>,
+) -> Result<Zeroizing<String>, Box<dyn Error>> {
+    let secret = Zeroizing::new(keypair.to_bytes());
+    Ok(match format {
+        ExportFormat::SolanaCli => Zeroizing::new(serde_json::to_string(secret.as_slice())?),
+        ExportFormat::Base58 => Zeroizing::new(bs58::encode(secret.as_slice()).into_string()),
+        ExportFormat::Mnemonic => {
+            let seed = seed.ok_or("This wallet was not imported from a seed phrase")?;
+            Zeroizing::new(format!(
+                "{}\n{}",
+                seed.phrase.as_str(),
+                seed.derivation_path
+            ))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PHRASE: &str = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+
+    fn keypair() -> Keypair {
+        keypair_from_seed(&[7; 32]).unwrap()
+    }
+
+    #[test]
+    fn solana_cli_json() {
+        let keypair = keypair();
+        let json = serde_json::to_string(keypair.to_bytes().as_slice()).unwrap();
+        let imported = import(json.as_bytes(), DEFAULT_DERIVATION_PATH).unwrap();
+        assert_eq!(imported.keypair.pubkey(), keypair.pubkey());
+        assert!(imported.seed.is_none());
+    }
+
+    #[test]
+    fn base58_key() {
+        let keypair = keypair();
+        let text = format!("{}\n", keypair.to_base58_string());
+        let imported = import(text.as_bytes(), DEFAULT_DERIVATION_PATH).unwrap();
+        assert_eq!(imported.keypair.pubkey(), keypair.pubkey());
+    }
+
+    #[test]
+    fn raw_bytes() {
+        let keypair = keypair();
+        let imported = import(&keypair.to_bytes(), DEFAULT_DERIVATION_PATH).unwrap();
+        assert_eq!(imported.keypair.pubkey(), keypair.pubkey());
+    }
+
+    #[test]
+    fn seed_phrase() {
+        let imported = import(PHRASE.as_bytes(), DEFAULT_DERIVATION_PATH).unwrap();
+        assert_eq!(
+            imported.keypair.pubkey().to_string(),
+            "HAgk14JpMQLgt6rVgv7cBQFJWFto5Dqxi472uT3DKpqk"
+        );
+        let seed = imported.seed.unwrap();
+        assert_eq!(seed.phrase.as_str(), PHRASE);
+        assert_eq!(seed.derivation_path, DEFAULT_DERIVATION_PATH);
+
+        let mut words: Vec<&str> = PHRASE.split(' ').collect();
+        words[11] = "abandon";
+        assert!(import(words.join(" ").as_bytes(), DEFAULT_DERIVATION_PATH).is_err());
+    }
+
+    #[test]
+    fn mismatched_public_key() {
+        let mut bytes = keypair().to_bytes();
+        bytes[32..].copy_from_slice(&keypair_from_seed(&[8; 32]).unwrap().pubkey().to_bytes());
+        assert!(import(&bytes, DEFAULT_DERIVATION_PATH).is_err());
+        let json = serde_json::to_string(bytes.as_slice()).unwrap();
+        assert!(import(json.as_bytes(), DEFAULT_DERIVATION_PATH).is_err());
+    }
+
+    #[test]
+    fn export_import() {
+        let path = "m/44'/501'/1'/0'";
+        let imported = import(PHRASE.as_bytes(), path).unwrap();
+        for format in [
+            ExportFormat::SolanaCli,
+            ExportFormat::Base58,
+            ExportFormat::Mnemonic,
+        ] {
+            let exported = export(format, &imported.keypair, imported.seed.as_ref()).unwrap();
+            // The path of a mnemonic export wins over the one passed in
+            let again = import(exported.as_bytes(), DEFAULT_DERIVATION_PATH).unwrap();
+            assert_eq!(
+                again.keypair.pubkey(),
+                imported.keypair.pubkey(),
+                "{format}"
+            );
+        }
+        assert!(export(ExportFormat::Mnemonic, &keypair(), None).is_err());
+    }
+}
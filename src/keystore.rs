@@ -0,0 +1,374 @@
+use std::{error::Error, fs, io::Write, path::Path};
+
+use aes_gcm_siv::{
+    aead::{Aead, NewAead, Payload},
+    Aes256GcmSiv, Key, Nonce,
+};
+use data_encoding::BASE64;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use solana_sdk::{signature::Keypair, signer::Signer};
+use zeroize::Zeroizing;
+
+use crate::{
+    config::WalletConfig,
+    keyfile::{self, ExportFormat, ImportedKey, SeedPhrase, DEFAULT_DERIVATION_PATH},
+};
+
+// Unlocks the keystores without a prompt, for unattended runs
+pub const PASSPHRASE_ENV: &str = "WALLET_PASSPHRASE";
+
+// scrypt cost, 2^15 iterations of 8 blocks takes ~100ms and 32MB
+const SCRYPT_LOG_N: u8 = 15;
+const SCRYPT_R: u32 = 8;
+const SCRYPT_P: u32 = 1;
+
+// Directory of the rolling log files
+const LOG_DIR: &str = "logs";
+
+// A keypair encrypted with a key derived from a passphrase
+#[derive(Serialize, Deserialize)]
+struct Keystore {
+    version: u32,
+    // Stored in the clear to tell wallets apart, authenticated with the secret
+    pubkey: String,
+    kdf: ScryptParams,
+    nonce: String,
+    ciphertext: String,
+    // Seed phrase of wallets imported from one, so it can be exported again
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    seed: Option<SealedSeed>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SealedSeed {
+    derivation_path: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ScryptParams {
+    log_n: u8,
+    r: u32,
+    p: u32,
+    salt: String,
+}
+
+// Asks for the passphrase once and reuses it for every wallet
+#[derive(Default)]
+pub struct Unlocker {
+    passphrase: Option<Zeroizing<String>>,
+}
+
+impl Unlocker {
+    fn passphrase(&mut self, new: bool) -> Result<&str, Box<dyn Error>> {
+        if self.passphrase.is_none() {
+            let passphrase = match std::env::var(PASSPHRASE_ENV) {
+                Ok(passphrase) => Zeroizing::new(passphrase),
+                Err(_) if new => {
+                    let passphrase =
+                        Zeroizing::new(rpassword::prompt_password("New wallet passphrase: ")?);
+                    let confirm =
+                        Zeroizing::new(rpassword::prompt_password("Repeat wallet passphrase: ")?);
+                    if passphrase != confirm {
+                        return Err("Passphrases do not match".into());
+                    }
+                    passphrase
+                }
+                Err(_) => Zeroizing::new(rpassword::prompt_password("Wallet passphrase: ")?),
+            };
+            if passphrase.is_empty() {
+                return Err("Wallet passphrase is empty".into());
+            }
+            self.passphrase = Some(passphrase);
+        }
+        Ok(self.passphrase.as_ref().unwrap())
+    }
+}
+
+// The keystore kept next to a raw keypair file, keypair.json -> keypair.keystore
+pub fn keystore_path(keypair_file: &str) -> String {
+    Path::new(keypair_file)
+        .with_extension("keystore")
+        .to_string_lossy()
+        .into_owned()
+}
+
+// Unlock a wallet. Its key file is moved into an encrypted keystore the first time,
+// a missing one is generated straight into a keystore unless `generate` is off.
+pub fn open_wallet(
+    wallet: &WalletConfig,
+    generate: bool,
+    unlocker: &mut Unlocker,
+) -> Result<Keypair, Box<dyn Error>> {
+    Ok(unlock(wallet, generate, unlocker)?.keypair)
+}
+
+// The secret of a wallet in `format`, only ever meant for stdout
+pub fn export_wallet(
+    wallet: &WalletConfig,
+    format: ExportFormat,
+    unlocker: &mut Unlocker,
+) -> Result<Zeroizing<String>, Box<dyn Error>> {
+    let key = unlock(wallet, false, unlocker)?;
+    keyfile::export(format, &key.keypair, key.seed.as_ref())
+}
+
+fn unlock(
+    wallet: &WalletConfig,
+    generate: bool,
+    unlocker: &mut Unlocker,
+) -> Result<ImportedKey, Box<dyn Error>> {
+    let keypair_file = wallet.keypair_file.as_str();
+    let path = keystore_path(keypair_file);
+    if Path::new(&path).exists() {
+        return decrypt(&fs::read_to_string(&path)?, unlocker.passphrase(false)?);
+    }
+
+    let raw = Path::new(keypair_file).exists();
+    let key = if raw {
+        let data = Zeroizing::new(fs::read(keypair_file)?);
+        let derivation_path = wallet
+            .derivation_path
+            .as_deref()
+            .unwrap_or(DEFAULT_DERIVATION_PATH);
+        keyfile::import(&data, derivation_path)
+            .map_err(|e| format!("Unable to parse {keypair_file}: {e}"))?
+    } else if generate {
+        ImportedKey {
+            keypair: Keypair::new(),
+            seed: None,
+        }
+    } else {
+        return Err(format!(
+            "Wallet {}: neither {keypair_file} nor {path} exists and wallet generation is refused",
+            wallet.name
+        )
+        .into());
+    };
+
+    let keystore = encrypt(&key, unlocker.passphrase(true)?, SCRYPT_LOG_N)?;
+    // Never drop the only copy of a secret before the keystore is known to open
+    let reopened = decrypt(&keystore, unlocker.passphrase(false)?)?;
+    if reopened.keypair.pubkey() != key.keypair.pubkey()
+        || reopened.seed.as_ref().map(|s| &s.phrase) != key.seed.as_ref().map(|s| &s.phrase)
+    {
+        return Err(format!("Keystore for {keypair_file} does not round-trip").into());
+    }
+    write_private(&path, keystore)?;
+
+    if raw {
+        // Overwrite the plaintext before removing it
+        let len = fs::metadata(keypair_file)?.len() as usize;
+        fs::write(keypair_file, vec![0u8; len])?;
+        fs::remove_file(keypair_file)?;
+        log::warn!(target:"app", "Encrypted {keypair_file} into {path} and removed the plaintext file");
+        tracing::info!("Migrated {keypair_file} to keystore {path}");
+        // Versions before the keystore wrote the secret key to the log files
+        if Path::new(LOG_DIR).exists() {
+            log::warn!(target:"app", "Delete the old {LOG_DIR}/app.log.* files, they may contain the secret key of {keypair_file}");
+        }
+    } else {
+        log::warn!(target:"app", "Created new wallet {} in {path}", key.keypair.pubkey());
+    }
+    Ok(key)
+}
+
+// Write a file only its owner can read
+pub fn write_private(path: &str, data: impl AsRef<[u8]>) -> std::io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(0o600);
+        // The mode only applies to new files, an existing one is restricted before it is written
+        if Path::new(path).exists() {
+            fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+        }
+    }
+    options.open(path)?.write_all(data.as_ref())
+}
+
+fn derive_key(
+    passphrase: &str,
+    params: &ScryptParams,
+) -> Result<Zeroizing<[u8; 32]>, Box<dyn Error>> {
+    let salt = BASE64.decode(params.salt.as_bytes())?;
+    let scrypt_params = scrypt::Params::new(params.log_n, params.r, params.p, 32)
+        .map_err(|e| format!("Invalid keystore scrypt parameters: {e}"))?;
+    let mut key = Zeroizing::new([0u8; 32]);
+    scrypt::scrypt(passphrase.as_bytes(), &salt, &scrypt_params, key.as_mut())
+        .map_err(|e| format!("Unable to derive keystore key: {e}"))?;
+    Ok(key)
+}
+
+fn seal(
+    key: &[u8; 32],
+    nonce: [u8; 12],
+    msg: &[u8],
+    aad: &[u8],
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    Ok(Aes256GcmSiv::new(&Key::from(*key))
+        .encrypt(&Nonce::from(nonce), Payload { msg, aad })
+        .map_err(|_| "Unable to encrypt keypair")?)
+}
+
+fn open(key: &[u8; 32], nonce: &str, ciphertext: &str, aad: &str) -> Option<Zeroizing<Vec<u8>>> {
+    let nonce: [u8; 12] = BASE64.decode(nonce.as_bytes()).ok()?.try_into().ok()?;
+    let ciphertext = BASE64.decode(ciphertext.as_bytes()).ok()?;
+    Aes256GcmSiv::new(&Key::from(*key))
+        .decrypt(
+            &Nonce::from(nonce),
+            Payload {
+                msg: &ciphertext,
+                aad: aad.as_bytes(),
+            },
+        )
+        .ok()
+        .map(Zeroizing::new)
+}
+
+fn random_nonce() -> [u8; 12] {
+    let mut nonce = [0u8; 12];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    nonce
+}
+
+fn encrypt(imported: &ImportedKey, passphrase: &str, log_n: u8) -> Result<String, Box<dyn Error>> {
+    let mut salt = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut salt);
+
+    let kdf = ScryptParams {
+        log_n,
+        r: SCRYPT_R,
+        p: SCRYPT_P,
+        salt: BASE64.encode(&salt),
+    };
+    let key = derive_key(passphrase, &kdf)?;
+    let pubkey = imported.keypair.pubkey().to_string();
+    let secret = Zeroizing::new(imported.keypair.to_bytes());
+    let nonce = random_nonce();
+    let ciphertext = seal(&key, nonce, secret.as_ref(), pubkey.as_bytes())?;
+
+    let seed = match &imported.seed {
+        Some(seed) => {
+            let nonce = random_nonce();
+            let ciphertext = seal(&key, nonce, seed.phrase.as_bytes(), pubkey.as_bytes())?;
+            Some(SealedSeed {
+                derivation_path: seed.derivation_path.clone(),
+                nonce: BASE64.encode(&nonce),
+                ciphertext: BASE64.encode(&ciphertext),
+            })
+        }
+        None => None,
+    };
+
+    Ok(serde_json::to_string_pretty(&Keystore {
+        version: 1,
+        pubkey,
+        kdf,
+        nonce: BASE64.encode(&nonce),
+        ciphertext: BASE64.encode(&ciphertext),
+        seed,
+    })?)
+}
+
+fn decrypt(data: &str, passphrase: &str) -> Result<ImportedKey, Box<dyn Error>> {
+    let keystore: Keystore = serde_json::from_str(data)?;
+    if keystore.version != 1 {
+        return Err(format!("Unsupported keystore version {}", keystore.version).into());
+    }
+
+    let key = derive_key(passphrase, &keystore.kdf)?;
+    let secret = open(
+        &key,
+        &keystore.nonce,
+        &keystore.ciphertext,
+        &keystore.pubkey,
+    )
+    .ok_or_else(|| format!("Wrong passphrase for wallet {}", keystore.pubkey))?;
+    let keypair = Keypair::from_bytes(&secret).map_err(|e| format!("Corrupt keystore: {e}"))?;
+
+    let seed = match keystore.seed {
+        Some(sealed) => {
+            let phrase = open(&key, &sealed.nonce, &sealed.ciphertext, &keystore.pubkey)
+                .ok_or_else(|| format!("Corrupt seed phrase in keystore {}", keystore.pubkey))?;
+            let phrase = String::from_utf8(phrase.to_vec())
+                .map_err(|_| format!("Corrupt seed phrase in keystore {}", keystore.pubkey))?;
+            Some(SeedPhrase {
+                phrase: Zeroizing::new(phrase),
+                derivation_path: sealed.derivation_path,
+            })
+        }
+        None => None,
+    };
+    Ok(ImportedKey { keypair, seed })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::signer::keypair::keypair_from_seed;
+
+    // Cheap enough for tests, real keystores use SCRYPT_LOG_N
+    const TEST_LOG_N: u8 = 4;
+
+    fn imported(seed: bool) -> ImportedKey {
+        ImportedKey {
+            keypair: keypair_from_seed(&[7; 32]).unwrap(),
+            seed: seed.then(|| SeedPhrase {
+                phrase: Zeroizing::new(
+                    "legal winner thank year wave sausage worth useful legal winner thank yellow"
+                        .into(),
+                ),
+                derivation_path: DEFAULT_DERIVATION_PATH.into(),
+            }),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        for seed in [false, true] {
+            let key = imported(seed);
+            let keystore = encrypt(&key, "hunter2", TEST_LOG_N).unwrap();
+            let opened = decrypt(&keystore, "hunter2").unwrap();
+            assert_eq!(opened.keypair.to_bytes(), key.keypair.to_bytes());
+            assert_eq!(
+                opened.seed.as_ref().map(|s| s.phrase.as_str()),
+                key.seed.as_ref().map(|s| s.phrase.as_str())
+            );
+        }
+    }
+
+    #[test]
+    fn wrong_passphrase() {
+        let keystore = encrypt(&imported(true), "hunter2", TEST_LOG_N).unwrap();
+        assert!(decrypt(&keystore, "hunter3").is_err());
+    }
+
+    #[test]
+    fn tampered_pubkey() {
+        let keystore = encrypt(&imported(false), "hunter2", TEST_LOG_N).unwrap();
+        let mut keystore: Keystore = serde_json::from_str(&keystore).unwrap();
+        keystore.pubkey = keypair_from_seed(&[8; 32]).unwrap().pubkey().to_string();
+        let keystore = serde_json::to_string(&keystore).unwrap();
+        assert!(decrypt(&keystore, "hunter2").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn private_file() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = std::env::temp_dir().join(format!("keystore-test-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        fs::write(path, "").unwrap();
+        fs::set_permissions(path, fs::Permissions::from_mode(0o644)).unwrap();
+        write_private(path, "secret").unwrap();
+        let mode = fs::metadata(path).unwrap().permissions().mode();
+        fs::remove_file(path).unwrap();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
@@ -0,0 +1,384 @@
+use std::{error::Error, str::FromStr, sync::OnceLock, time::Duration};
+
+use reqwest::Url;
+use serde_json::Value;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::{
+    positions::TOKEN_2022_PROGRAM_ID,
+    pricing::{RaydiumPool, RAYDIUM_AMM_PROGRAM_ID, WSOL_MINT},
+};
+
+// Pool lookups run before a buy, give up rather than hold the trade back
+const DEXSCREENER_TIMEOUT: Duration = Duration::from_secs(3);
+
+// One client for every pool lookup, so connections are reused
+static HTTP_CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+
+// What the address in a link points at
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkAddress {
+    Mint(String),
+    Pool(String),
+    // Sites that accept either a mint or a pool under the same path
+    Any(String),
+}
+
+// Pulls the address out of one site's links
+struct LinkPattern {
+    hosts: &'static [&'static str],
+    extract: fn(&Url) -> Option<LinkAddress>,
+}
+
+const PATTERNS: [LinkPattern; 7] = [
+    LinkPattern {
+        hosts: &["pump.fun"],
+        extract: pump_fun,
+    },
+    LinkPattern {
+        hosts: &["dexscreener.com"],
+        extract: dexscreener,
+    },
+    LinkPattern {
+        hosts: &["birdeye.so"],
+        extract: birdeye,
+    },
+    LinkPattern {
+        hosts: &["solscan.io"],
+        extract: solscan,
+    },
+    LinkPattern {
+        hosts: &["explorer.solana.com"],
+        extract: solana_explorer,
+    },
+    LinkPattern {
+        hosts: &["jup.ag"],
+        extract: jupiter,
+    },
+    LinkPattern {
+        hosts: &["raydium.io"],
+        extract: raydium,
+    },
+];
+
+// Mints that are never the token being called
+pub const QUOTE_MINTS: [&str; 3] = [
+    WSOL_MINT,
+    "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v",
+    "Es9vMFrzaCERmJfrF4H2FYD4KCoNkY11McCe8BenwNYB",
+];
+
+// Find the address a link from a known site points at, None for other sites
+pub fn parse(url: &str) -> Option<LinkAddress> {
+    let url = Url::parse(url).ok()?;
+    let host = url.host_str()?.trim_start_matches("www.");
+    let pattern = PATTERNS.iter().find(|p| {
+        p.hosts
+            .iter()
+            .any(|h| host == *h || host.ends_with(&format!(".{h}")))
+    })?;
+    (pattern.extract)(&url)
+}
+
+fn segments(url: &Url) -> Vec<&str> {
+    url.path_segments()
+        .map(|s| s.filter(|s| !s.is_empty()).collect())
+        .unwrap_or_default()
+}
+
+fn query(url: &Url, keys: &[&str]) -> Option<String> {
+    url.query_pairs()
+        .find(|(k, _)| keys.iter().any(|key| k.eq_ignore_ascii_case(key)))
+        .map(|(_, v)| v.into_owned())
+}
+
+// Only strings decoding to a 32-byte key, and not a quote token
+fn address(s: &str) -> Option<String> {
+    Pubkey::from_str(s).ok()?;
+    (!QUOTE_MINTS.contains(&s)).then(|| s.to_string())
+}
+
+// pump.fun/coin/<mint>, pump.fun/<mint>
+fn pump_fun(url: &Url) -> Option<LinkAddress> {
+    match segments(url).as_slice() {
+        ["coin", mint, ..] | [mint] => address(mint).map(LinkAddress::Mint),
+        _ => None,
+    }
+}
+
+// dexscreener.com/solana/<pair or mint>
+fn dexscreener(url: &Url) -> Option<LinkAddress> {
+    match segments(url).as_slice() {
+        ["solana", pair, ..] => address(pair).map(LinkAddress::Any),
+        _ => None,
+    }
+}
+
+// birdeye.so/token/<mint>?chain=solana, birdeye.so/solana/token/<mint>
+fn birdeye(url: &Url) -> Option<LinkAddress> {
+    if query(url, &["chain"]).is_some_and(|c| c != "solana") {
+        return None;
+    }
+    match segments(url).as_slice() {
+        ["token", mint, ..] | ["solana", "token", mint, ..] => address(mint).map(LinkAddress::Mint),
+        _ => None,
+    }
+}
+
+// solscan.io/token/<mint>, solscan.io/account/<address>
+fn solscan(url: &Url) -> Option<LinkAddress> {
+    match segments(url).as_slice() {
+        ["token", mint, ..] => address(mint).map(LinkAddress::Mint),
+        ["account", account, ..] => address(account).map(LinkAddress::Any),
+        _ => None,
+    }
+}
+
+// explorer.solana.com/address/<address>
+fn solana_explorer(url: &Url) -> Option<LinkAddress> {
+    match segments(url).as_slice() {
+        ["address", account, ..] => address(account).map(LinkAddress::Any),
+        _ => None,
+    }
+}
+
+// jup.ag/swap/SOL-<mint>, jup.ag/swap?sell=SOL&buy=<mint>, jup.ag/tokens/<mint>
+fn jupiter(url: &Url) -> Option<LinkAddress> {
+    if let Some(mint) = query(url, &["buy", "outputMint"]).and_then(|m| address(&m)) {
+        return Some(LinkAddress::Mint(mint));
+    }
+    match segments(url).as_slice() {
+        ["swap", pair, ..] => {
+            let (input, output) = pair.split_once('-').unwrap_or(("", pair));
+            address(output)
+                .or_else(|| address(input))
+                .map(LinkAddress::Mint)
+        }
+        ["tokens", mint, ..] => address(mint).map(LinkAddress::Mint),
+        _ => None,
+    }
+}
+
+// raydium.io/swap/?inputMint=sol&outputMint=<mint>, older links use inputCurrency/outputCurrency.
+// Liquidity pages link the pool, raydium.io/liquidity/increase/?pool_id=<pool>
+fn raydium(url: &Url) -> Option<LinkAddress> {
+    if let Some(pool) = query(url, &["pool_id"]).and_then(|p| address(&p)) {
+        return Some(LinkAddress::Pool(pool));
+    }
+    let output = query(url, &["outputMint", "outputCurrency"]).and_then(|m| address(&m));
+    let input = query(url, &["inputMint", "inputCurrency"]).and_then(|m| address(&m));
+    output.or(input).map(LinkAddress::Mint)
+}
+
+// The mint a link points at, resolving pools to their base token
+pub async fn resolve(
+    rpc_client: &RpcClient,
+    link: LinkAddress,
+) -> Result<Option<Pubkey>, Box<dyn Error + Send + Sync>> {
+    match link {
+        LinkAddress::Mint(mint) => Ok(Some(Pubkey::from_str(&mint)?)),
+        LinkAddress::Pool(pool) => resolve_pool(rpc_client, &Pubkey::from_str(&pool)?).await,
+        LinkAddress::Any(address) => {
+            let address = Pubkey::from_str(&address)?;
+            let account = rpc_client.get_account(&address).await?;
+            if account.owner == spl_token::id()
+                || account.owner == Pubkey::from_str(TOKEN_2022_PROGRAM_ID)?
+            {
+                return Ok(Some(address));
+            }
+            resolve_pool(rpc_client, &address).await
+        }
+    }
+}
+
+// Base mint of a pool, read from Raydium AMM accounts directly and from DexScreener otherwise
+async fn resolve_pool(
+    rpc_client: &RpcClient,
+    pool: &Pubkey,
+) -> Result<Option<Pubkey>, Box<dyn Error + Send + Sync>> {
+    let account = rpc_client.get_account(pool).await?;
+    if account.owner == Pubkey::from_str(RAYDIUM_AMM_PROGRAM_ID)? {
+        let pool = RaydiumPool::from_account_data(&account.data)?;
+        let wsol = Pubkey::from_str(WSOL_MINT)?;
+        return Ok(Some(if pool.base_mint == wsol {
+            pool.quote_mint
+        } else {
+            pool.base_mint
+        }));
+    }
+
+    let client = HTTP_CLIENT.get_or_init(|| {
+        reqwest::Client::builder()
+            .timeout(DEXSCREENER_TIMEOUT)
+            .connect_timeout(DEXSCREENER_TIMEOUT)
+            .build()
+            .unwrap()
+    });
+    let data: Value = client
+        .get(format!(
+            "https://api.dexscreener.com/latest/dex/pairs/solana/{pool}"
+        ))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let pair = &data["pairs"][0];
+    let base = pair["baseToken"]["address"].as_str();
+    let quote = pair["quoteToken"]["address"].as_str();
+    let mint = match (base, quote) {
+        (Some(base), Some(quote)) if QUOTE_MINTS.contains(&base) => quote,
+        (Some(base), _) => base,
+        _ => return Ok(None),
+    };
+    Ok(Some(Pubkey::from_str(mint)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINT: &str = "7GCihgDB8fe6KNjn2MYtkzZcRjQy3t9GHdC8uHYmW2hr";
+    const PUMP_MINT: &str = "HeLp6NuQkmYB4pYWo2zYs22mESHXPQYzXbB8n4V98jwC";
+    const PAIR: &str = "8sLbNZoA1cfnvMJLPfp98ZLAnFSYCFApfJKMbiXNLwxj";
+
+    fn check(cases: &[(&str, Option<LinkAddress>)]) {
+        for (url, expected) in cases {
+            assert_eq!(&parse(url), expected, "{url}");
+        }
+    }
+
+    fn mint(s: &str) -> Option<LinkAddress> {
+        Some(LinkAddress::Mint(s.to_string()))
+    }
+
+    fn any(s: &str) -> Option<LinkAddress> {
+        Some(LinkAddress::Any(s.to_string()))
+    }
+
+    #[test]
+    fn pump_fun_links() {
+        check(&[
+            (&format!("https://pump.fun/{PUMP_MINT}"), mint(PUMP_MINT)),
+            (
+                &format!("https://pump.fun/coin/{PUMP_MINT}"),
+                mint(PUMP_MINT),
+            ),
+            (
+                &format!("https://www.pump.fun/coin/{MINT}?include-nsfw=true"),
+                mint(MINT),
+            ),
+            ("https://pump.fun/board", None),
+            ("https://pump.fun/coin/notanaddress", None),
+        ]);
+    }
+
+    #[test]
+    fn dexscreener_links() {
+        check(&[
+            (&format!("https://dexscreener.com/solana/{PAIR}"), any(PAIR)),
+            (
+                &format!("https://dexscreener.com/solana/{MINT}?maker=abc"),
+                any(MINT),
+            ),
+            (&format!("https://dexscreener.com/ethereum/{PAIR}"), None),
+            ("https://dexscreener.com/solana", None),
+        ]);
+    }
+
+    #[test]
+    fn birdeye_links() {
+        check(&[
+            (
+                &format!("https://birdeye.so/token/{MINT}?chain=solana"),
+                mint(MINT),
+            ),
+            (&format!("https://birdeye.so/token/{MINT}"), mint(MINT)),
+            (
+                &format!("https://birdeye.so/solana/token/{MINT}"),
+                mint(MINT),
+            ),
+            (&format!("https://birdeye.so/token/{MINT}?chain=base"), None),
+            ("https://birdeye.so/find-gems", None),
+        ]);
+    }
+
+    #[test]
+    fn solscan_links() {
+        check(&[
+            (&format!("https://solscan.io/token/{MINT}"), mint(MINT)),
+            (
+                &format!("https://solscan.io/token/{MINT}#holders"),
+                mint(MINT),
+            ),
+            (&format!("https://solscan.io/account/{PAIR}"), any(PAIR)),
+            (&format!("https://solscan.io/tx/{PAIR}"), None),
+        ]);
+    }
+
+    #[test]
+    fn solana_explorer_links() {
+        check(&[
+            (
+                &format!("https://explorer.solana.com/address/{MINT}"),
+                any(MINT),
+            ),
+            (
+                &format!("https://explorer.solana.com/address/{MINT}/tokens?cluster=mainnet"),
+                any(MINT),
+            ),
+            (&format!("https://explorer.solana.com/tx/{PAIR}"), None),
+        ]);
+    }
+
+    #[test]
+    fn jupiter_links() {
+        check(&[
+            (&format!("https://jup.ag/swap/SOL-{MINT}"), mint(MINT)),
+            (
+                &format!("https://jup.ag/swap/{WSOL_MINT}-{MINT}"),
+                mint(MINT),
+            ),
+            (&format!("https://jup.ag/swap/{MINT}-SOL"), mint(MINT)),
+            (
+                &format!("https://jup.ag/swap?sell={WSOL_MINT}&buy={MINT}"),
+                mint(MINT),
+            ),
+            (&format!("https://jup.ag/tokens/{MINT}"), mint(MINT)),
+            ("https://jup.ag/swap/SOL-USDC", None),
+        ]);
+    }
+
+    #[test]
+    fn raydium_links() {
+        check(&[
+            (
+                &format!("https://raydium.io/swap/?inputMint=sol&outputMint={MINT}"),
+                mint(MINT),
+            ),
+            (
+                &format!("https://raydium.io/swap/?inputCurrency=sol&outputCurrency={MINT}"),
+                mint(MINT),
+            ),
+            (
+                &format!("https://raydium.io/swap/?inputMint={MINT}&outputMint=sol"),
+                mint(MINT),
+            ),
+            (
+                &format!("https://raydium.io/liquidity/increase/?mode=add&pool_id={PAIR}"),
+                Some(LinkAddress::Pool(PAIR.to_string())),
+            ),
+            ("https://raydium.io/liquidity-pools/", None),
+        ]);
+    }
+
+    #[test]
+    fn other_sites() {
+        check(&[
+            (&format!("https://example.com/coin/{MINT}"), None),
+            (&format!("https://notpump.fun/coin/{MINT}"), None),
+            ("not a url", None),
+        ]);
+    }
+}
@@ -1,44 +1,81 @@
 mod config;
+mod confirm;
+mod dedup;
+mod expander;
+mod filters;
+mod graphql;
+mod journal;
+mod keyfile;
+mod keystore;
+mod links;
+mod market;
+mod positions;
+mod pricing;
+mod safety;
+mod signals;
+mod strategy;
 mod tasks;
 mod twitter;
 mod ui;
+mod wallets;
+mod x_api;
 
-use crate::config::{generate_keypair_if_not_exists, load_or_create_config};
+use crate::config::load_or_create_config;
 use crate::ui::run_ui;
 use config::Config;
+use dedup::BuyGuard;
+use expander::UrlExpander;
+use journal::Journal;
 use reqwest::Client;
 use reqwest_cookie_store::CookieStoreMutex;
 use serde_json::{json, Value};
+use signals::{MonitorHealth, SignalSource, StatusUpdate};
 use solana_client::nonblocking::rpc_client::RpcClient;
 use solana_client::nonblocking::tpu_client::TpuClient;
 use solana_client::tpu_client::TpuClientConfig;
 use solana_sdk::commitment_config::CommitmentConfig;
-use solana_sdk::signature::Keypair;
 use std::sync::mpsc::{Receiver, Sender};
-use std::sync::{mpsc, Arc};
+use std::sync::{mpsc, Arc, Mutex};
+use strategy::ExitTracker;
 use tmc_solana_engine::jupiter::JupiterEngine;
 use tmc_solana_engine::pumpfun::PumpFunEngine;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
 use tracing::level_filters::LevelFilter;
 use tracing_appender::non_blocking::{NonBlocking, WorkerGuard};
 use tracing_appender::rolling::{RollingFileAppender, Rotation};
 use tracing_subscriber::fmt;
 use tracing_subscriber::prelude::*;
 use ui::UserInfo;
+use wallets::{Wallet, Wallets};
 
 pub struct State {
     config: Config,
-    wallet: Keypair,
+    wallets: Wallets,
+    rpc_client: Arc<RpcClient>,
     jupiter_engine: JupiterEngine,
     pumpfun_engine: PumpFunEngine,
+    journal: Arc<Mutex<Journal>>,
+    exit_tracker: Arc<Mutex<ExitTracker>>,
+    buy_guard: Arc<Mutex<BuyGuard>>,
+    monitor_health: MonitorHealth,
+    url_expander: Arc<UrlExpander>,
+    status_updates: UnboundedSender<StatusUpdate>,
 }
 
 impl Clone for State {
     fn clone(&self) -> Self {
         Self {
             config: self.config.clone(),
-            wallet: self.wallet.insecure_clone(),
+            wallets: self.wallets.clone(),
+            rpc_client: self.rpc_client.clone(),
             jupiter_engine: self.jupiter_engine.clone(),
             pumpfun_engine: self.pumpfun_engine.clone(),
+            journal: self.journal.clone(),
+            exit_tracker: self.exit_tracker.clone(),
+            buy_guard: self.buy_guard.clone(),
+            monitor_health: self.monitor_health.clone(),
+            url_expander: self.url_expander.clone(),
+            status_updates: self.status_updates.clone(),
         }
     }
 }
@@ -75,18 +112,57 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     tui_logger::set_default_level(log::LevelFilter::Trace);
 
     let config = load_or_create_config("config.json");
-    let keypair: Keypair = generate_keypair_if_not_exists("keypair.json");
-    tracing::info!("{}", keypair.to_base58_string());
+    config.validate()?;
+    let mut unlocker = keystore::Unlocker::default();
+
+    // `export <wallet> <solana-cli|base58|mnemonic>` prints a wallet secret and exits
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("export") {
+        let (Some(name), Some(format)) = (args.get(2), args.get(3)) else {
+            return Err("Usage: export <wallet> <solana-cli|base58|mnemonic>".into());
+        };
+        let format: keyfile::ExportFormat = format.parse()?;
+        let wallet = config
+            .wallet_configs()
+            .into_iter()
+            .find(|w| &w.name == name)
+            .ok_or_else(|| format!("Unknown wallet {name}"))?;
+        let secret = keystore::export_wallet(&wallet, format, &mut unlocker)?;
+        println!("{}", secret.as_str());
+        return Ok(());
+    }
+
+    let wallets = config
+        .wallet_configs()
+        .iter()
+        .map(|w| {
+            Ok(Wallet {
+                name: w.name.clone(),
+                keypair: keystore::open_wallet(w, !config.refuse_wallet_generation, &mut unlocker)?,
+            })
+        })
+        .collect::<Result<Vec<_>, Box<dyn std::error::Error>>>()?;
+    drop(unlocker);
+    let wallets = Wallets::new(wallets);
+    for wallet in wallets.all() {
+        tracing::info!("Wallet {}: {}", wallet.name, wallet.pubkey());
+    }
+    let default_wallet = wallets.default_wallet().pubkey();
 
     auth(config.license.clone()).await?;
     log::info!(target:"app", "Logged in!");
 
+    if config.paper_trading {
+        log::warn!(target:"app", "Paper trading enabled, no real trades will be sent!");
+    }
+
     let rpc_client = Arc::new(RpcClient::new_with_commitment(
         config.rpc_url.clone(),
         CommitmentConfig::confirmed(),
     ));
 
     let (sender, receiver): (Sender<Vec<UserInfo>>, Receiver<Vec<UserInfo>>) = mpsc::channel();
+    let (status_sender, status_receiver) = unbounded_channel();
     let jupiter_engine = tmc_solana_engine::jupiter::JupiterEngine {
         rpc_client: rpc_client.clone(),
     };
@@ -105,19 +181,47 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let state = State {
         config: config.clone(),
-        wallet: keypair,
+        wallets,
+        rpc_client: rpc_client.clone(),
         jupiter_engine,
         pumpfun_engine,
+        journal: Arc::new(Mutex::new(Journal::load_or_create(
+            "trades.jsonl",
+            &default_wallet,
+        ))),
+        exit_tracker: Arc::new(Mutex::new(ExitTracker::load_or_create(
+            "exit_state.json",
+            &default_wallet,
+        ))),
+        buy_guard: Arc::new(Mutex::new(BuyGuard::default())),
+        monitor_health: MonitorHealth::default(),
+        url_expander: Arc::new(UrlExpander::new()?),
+        status_updates: status_sender,
     };
 
-    let state_cloned = state.clone();
-    tokio::spawn(async move {
-        let cookie_store = Arc::new(CookieStoreMutex::default());
-        twitter::monitor(sender, cookie_store, state_cloned)
-            .await
-            .unwrap();
-    });
-    run_ui(state.wallet.insecure_clone(), state, receiver, rpc_client).await?;
+    let users: Vec<String> = config.users.iter().map(|u| u.name().to_string()).collect();
+    let mut sources: Vec<Box<dyn SignalSource>> = vec![];
+    if let Some(twitter_config) = config.twitter.clone() {
+        sources.push(Box::new(twitter::TwitterSource::new(
+            twitter_config,
+            Arc::new(CookieStoreMutex::default()),
+            users.clone(),
+        )));
+    }
+    if let Some(x_api_config) = config.x_api.clone() {
+        sources.push(Box::new(x_api::XApiSource::new(x_api_config, users)));
+    }
+    if sources.is_empty() {
+        log::error!(target:"app", "No signal source configured, add a twitter or x_api section to config.json");
+    }
+
+    tokio::spawn(signals::run(
+        sources,
+        status_receiver,
+        sender,
+        state.clone(),
+    ));
+    run_ui(state, receiver, rpc_client).await?;
 
     Ok(())
 }
@@ -0,0 +1,212 @@
+use std::{
+    error::Error,
+    fmt,
+    num::NonZeroUsize,
+    str::FromStr,
+    sync::{Mutex, OnceLock},
+    time::Duration,
+};
+
+use lru::LruCache;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+use tokio::time::timeout;
+
+use crate::pricing::{self, BondingCurve, WSOL_MINT};
+
+// Amount used to probe Jupiter for a route, 0.01 SOL
+const PROBE_LAMPORTS: u64 = 10_000_000;
+// Lookups run before every buy, a slow one must not hold the trade back
+const HTTP_TIMEOUT: Duration = Duration::from_secs(5);
+const POOL_SEARCH_TIMEOUT: Duration = Duration::from_secs(10);
+const CACHE_SIZE: usize = 1024;
+
+// One client for every lookup, so connections are reused
+static HTTP_CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+// Venues found for recent mints. Only the bonding curve is left, so those are looked up again.
+static MARKETS: OnceLock<Mutex<LruCache<Pubkey, Market>>> = OnceLock::new();
+
+// Where a token trades
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Market {
+    // Still on the pump.fun bonding curve
+    PumpFun,
+    // Graduated from pump.fun to a Raydium pool
+    #[serde(alias = "Raydium")]
+    PumpFunMigrated,
+    RaydiumAmm,
+    RaydiumClmm,
+    Meteora,
+    Orca,
+    // Any other venue Jupiter has a route through
+    OtherDex,
+    // No liquidity found
+    #[default]
+    #[serde(other)]
+    Unknown,
+}
+
+impl Market {
+    // Markets bought and sold through the pump.fun program directly
+    pub fn is_bonding_curve(&self) -> bool {
+        *self == Market::PumpFun
+    }
+
+    // Markets backed by a Raydium AMM v4 pool
+    pub fn is_raydium_amm(&self) -> bool {
+        matches!(self, Market::PumpFunMigrated | Market::RaydiumAmm)
+    }
+}
+
+impl fmt::Display for Market {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Market::PumpFun => "PumpFun",
+            Market::PumpFunMigrated => "PumpFun (Raydium)",
+            Market::RaydiumAmm => "Raydium AMM",
+            Market::RaydiumClmm => "Raydium CLMM",
+            Market::Meteora => "Meteora",
+            Market::Orca => "Orca",
+            Market::OtherDex => "Jupiter",
+            Market::Unknown => "Unknown",
+        };
+        write!(f, "{name}")
+    }
+}
+
+// Find where a mint trades: the pump.fun curve first, then a Jupiter route, and only
+// without a route a Raydium pool search, for pools Jupiter has not picked up yet
+pub async fn identify(
+    rpc_client: &RpcClient,
+    mint: &str,
+) -> Result<Market, Box<dyn Error + Send + Sync>> {
+    let mint = Pubkey::from_str(mint)?;
+    let markets =
+        MARKETS.get_or_init(|| Mutex::new(LruCache::new(NonZeroUsize::new(CACHE_SIZE).unwrap())));
+    if let Some(market) = markets.lock().unwrap().get(&mint) {
+        return Ok(*market);
+    }
+
+    // Read on chain, a missing account means the mint never was on pump.fun
+    let curve = rpc_client
+        .get_account_with_commitment(
+            &pricing::bonding_curve_address(&mint),
+            rpc_client.commitment(),
+        )
+        .await?
+        .value
+        .map(|account| BondingCurve::from_account_data(&account.data))
+        .transpose()?;
+    let graduated = match curve {
+        Some(curve) if !curve.complete => return Ok(Market::PumpFun),
+        curve => curve.is_some(),
+    };
+
+    let market = match jupiter_route_label(&mint).await? {
+        Some(label) => route_market(&label, graduated),
+        None => {
+            let pool = timeout(
+                POOL_SEARCH_TIMEOUT,
+                pricing::find_raydium_pool(rpc_client, &mint),
+            )
+            .await
+            .map_err(|_| format!("Raydium pool search for {mint} timed out"))??;
+            match pool {
+                Some(_) if graduated => Market::PumpFunMigrated,
+                Some(_) => Market::RaydiumAmm,
+                None => Market::Unknown,
+            }
+        }
+    };
+    // A mint without liquidity may still get some
+    if market != Market::Unknown {
+        markets.lock().unwrap().put(mint, market);
+    }
+    Ok(market)
+}
+
+// The market of the first venue of a Jupiter route
+fn route_market(label: &str, graduated: bool) -> Market {
+    let label = label.to_lowercase();
+    if label.contains("raydium") && label.contains("clmm") {
+        Market::RaydiumClmm
+    } else if label.contains("raydium") && graduated {
+        Market::PumpFunMigrated
+    } else if label.contains("raydium") {
+        Market::RaydiumAmm
+    } else if label.contains("meteora") {
+        Market::Meteora
+    } else if label.contains("orca") || label.contains("whirlpool") {
+        Market::Orca
+    } else {
+        Market::OtherDex
+    }
+}
+
+// Label of the first venue of a Jupiter SOL -> mint route, None when there is no route
+async fn jupiter_route_label(
+    mint: &Pubkey,
+) -> Result<Option<String>, Box<dyn Error + Send + Sync>> {
+    let client = HTTP_CLIENT.get_or_init(|| {
+        reqwest::Client::builder()
+            .timeout(HTTP_TIMEOUT)
+            .connect_timeout(HTTP_TIMEOUT)
+            .build()
+            .unwrap()
+    });
+    let response = client
+        .get(format!(
+            "https://quote-api.jup.ag/v6/quote?inputMint={WSOL_MINT}&outputMint={mint}&amount={PROBE_LAMPORTS}&slippageBps=50"
+        ))
+        .header("Accept", "application/json")
+        .send()
+        .await?;
+
+    // Jupiter answers 400 when it cannot route the pair, rate limits and outages are errors
+    if response.status() == reqwest::StatusCode::BAD_REQUEST {
+        return Ok(None);
+    }
+    let data: Value = response.error_for_status()?.json().await?;
+    Ok(data["routePlan"][0]["swapInfo"]["label"]
+        .as_str()
+        .map(|label| label.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn route_labels() {
+        for (label, graduated, market) in [
+            ("Raydium", false, Market::RaydiumAmm),
+            ("Raydium", true, Market::PumpFunMigrated),
+            ("Raydium CLMM", false, Market::RaydiumClmm),
+            ("Raydium CLMM", true, Market::RaydiumClmm),
+            ("Meteora DLMM", false, Market::Meteora),
+            ("Orca V2", false, Market::Orca),
+            ("Whirlpool", false, Market::Orca),
+            ("Pump.fun Amm", true, Market::OtherDex),
+            ("Phoenix", false, Market::OtherDex),
+        ] {
+            assert_eq!(route_market(label, graduated), market, "{label}");
+        }
+    }
+
+    #[test]
+    fn serde_names() {
+        let parse = |name: &str| serde_json::from_str::<Market>(&format!("\"{name}\"")).unwrap();
+        assert_eq!(parse("PumpFun"), Market::PumpFun);
+        assert_eq!(parse("PumpFunMigrated"), Market::PumpFunMigrated);
+        // Journals written before the enum stored "Raydium" for graduated tokens
+        assert_eq!(parse("Raydium"), Market::PumpFunMigrated);
+        assert_eq!(parse("Jupiter"), Market::Unknown);
+        assert_eq!(parse("Unknown"), Market::Unknown);
+        assert_eq!(
+            serde_json::to_string(&Market::RaydiumClmm).unwrap(),
+            "\"RaydiumClmm\""
+        );
+    }
+}
@@ -0,0 +1,286 @@
+use solana_account_decoder::UiAccountData;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_request::TokenAccountsFilter;
+use solana_sdk::pubkey::Pubkey;
+use std::collections::HashMap;
+use std::{error::Error, str::FromStr, time::Duration};
+use tokio::time::Instant;
+
+use crate::market::Market;
+use crate::pricing::{self, BondingCurve, RaydiumPool};
+use crate::State;
+
+pub const TOKEN_2022_PROGRAM_ID: &str = "TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb";
+const METADATA_PROGRAM_ID: &str = "metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s";
+// How long a mint without a Raydium pool is not searched again, it may still migrate
+const NO_POOL_RECHECK: Duration = Duration::from_secs(300);
+
+// A held token, valued from its bonding curve, pool or a Jupiter quote and costed from the journal
+#[derive(Debug, Clone)]
+pub struct Position {
+    // Public key of the holding wallet
+    pub wallet: String,
+    pub mint: String,
+    pub symbol: String,
+    pub market: Market,
+    pub amount: f64,
+    // None while no price could be found, such positions are never evaluated for exits
+    pub price_sol: Option<f64>,
+    pub value_sol: Option<f64>,
+    pub cost_basis: f64,
+    pub pnl_pct: Option<f64>,
+    pub simulated: bool,
+}
+
+impl Position {
+    fn new(wallet: String, mint: String, amount: f64, cost_basis: f64, simulated: bool) -> Self {
+        Self {
+            wallet,
+            mint,
+            symbol: String::new(),
+            market: Market::Unknown,
+            amount,
+            price_sol: None,
+            value_sol: None,
+            cost_basis,
+            pnl_pct: None,
+            simulated,
+        }
+    }
+}
+
+// Keeps symbols and pool addresses between refreshes, they rarely change
+#[derive(Default)]
+pub struct PositionTracker {
+    symbols: HashMap<String, String>,
+    pools: HashMap<String, RaydiumPool>,
+    // Mints found without a pool, and when
+    no_pool: HashMap<String, Instant>,
+}
+
+impl PositionTracker {
+    // All non-empty SPL Token and Token-2022 accounts of `owner`
+    pub async fn get_positions(
+        &mut self,
+        state: &State,
+        owner: &Pubkey,
+    ) -> Result<Vec<Position>, Box<dyn Error + Send + Sync>> {
+        let rpc_client = &state.rpc_client;
+        let mut balances: HashMap<String, f64> = HashMap::new();
+
+        for program_id in [spl_token::id(), Pubkey::from_str(TOKEN_2022_PROGRAM_ID)?] {
+            let accounts = rpc_client
+                .get_token_accounts_by_owner(owner, TokenAccountsFilter::ProgramId(program_id))
+                .await?;
+            for keyed in accounts {
+                let UiAccountData::Json(parsed) = keyed.account.data else {
+                    continue;
+                };
+                let info = &parsed.parsed["info"];
+                let Some(mint) = info["mint"].as_str() else {
+                    continue;
+                };
+                let amount = info["tokenAmount"]["uiAmount"].as_f64().unwrap_or(0.0);
+                if amount < 0.0000001 {
+                    continue;
+                }
+                *balances.entry(mint.to_string()).or_default() += amount;
+            }
+        }
+
+        let wallet = owner.to_string();
+        let books = state
+            .journal
+            .lock()
+            .unwrap()
+            .wallet_positions(&wallet, false);
+        let positions = balances
+            .into_iter()
+            .map(|(mint, amount)| {
+                let cost_basis = books.get(&mint).map(|b| b.cost_basis).unwrap_or(0.0);
+                Position::new(wallet.clone(), mint, amount, cost_basis, false)
+            })
+            .collect();
+
+        self.price(rpc_client, positions).await
+    }
+
+    // Open simulated positions of `owner` from the journal
+    pub async fn get_paper_positions(
+        &mut self,
+        state: &State,
+        owner: &Pubkey,
+    ) -> Result<Vec<Position>, Box<dyn Error + Send + Sync>> {
+        let positions = state
+            .journal
+            .lock()
+            .unwrap()
+            .open_positions(&owner.to_string(), true)
+            .into_iter()
+            .map(|book| Position::new(book.wallet, book.mint, book.tokens, book.cost_basis, true))
+            .collect();
+
+        self.price(&state.rpc_client, positions).await
+    }
+
+    async fn price(
+        &mut self,
+        rpc_client: &RpcClient,
+        mut positions: Vec<Position>,
+    ) -> Result<Vec<Position>, Box<dyn Error + Send + Sync>> {
+        let mints = positions
+            .iter()
+            .map(|p| Pubkey::from_str(&p.mint))
+            .collect::<Result<Vec<Pubkey>, _>>()?;
+        let curves = get_bonding_curves(rpc_client, &mints).await?;
+
+        for ((position, mint), curve) in positions.iter_mut().zip(mints.iter()).zip(curves) {
+            position.symbol = self.symbol(rpc_client, mint).await;
+
+            match curve {
+                Some(curve) if !curve.complete => {
+                    position.market = Market::PumpFun;
+                    position.price_sol = Some(curve.price());
+                }
+                curve => {
+                    // A completed curve means the token graduated from pump.fun
+                    let graduated = curve.is_some();
+                    if let Some(pool) = self.pool(rpc_client, mint).await {
+                        position.market = if graduated {
+                            Market::PumpFunMigrated
+                        } else {
+                            Market::RaydiumAmm
+                        };
+                        position.price_sol = match pool.price(rpc_client, mint).await {
+                            Ok(price) => Some(price),
+                            Err(e) => {
+                                tracing::warn!("Unable to price {mint} from its pool: {e}");
+                                None
+                            }
+                        };
+                    }
+                    // Other venues, and pools that failed to price, go through Jupiter
+                    if position.price_sol.is_none() && position.amount > 0.0 {
+                        match pricing::quote_sell(
+                            rpc_client,
+                            Market::OtherDex,
+                            mint,
+                            position.amount,
+                        )
+                        .await
+                        {
+                            Ok(sol) => {
+                                if position.market == Market::Unknown {
+                                    position.market = if graduated {
+                                        Market::PumpFunMigrated
+                                    } else {
+                                        Market::OtherDex
+                                    };
+                                }
+                                position.price_sol = Some(sol / position.amount);
+                            }
+                            Err(e) => tracing::warn!("Unable to price {mint} through Jupiter: {e}"),
+                        }
+                    }
+                }
+            }
+
+            position.value_sol = position.price_sol.map(|price| position.amount * price);
+            position.pnl_pct = position.value_sol.map(|value| {
+                if position.cost_basis > 0.0 {
+                    ((value / position.cost_basis) - 1.0) * 100.0
+                } else {
+                    0.0
+                }
+            });
+        }
+
+        Ok(positions)
+    }
+
+    async fn pool(&mut self, rpc_client: &RpcClient, mint: &Pubkey) -> Option<RaydiumPool> {
+        let key = mint.to_string();
+        if let Some(pool) = self.pools.get(&key) {
+            return Some(pool.clone());
+        }
+        if self
+            .no_pool
+            .get(&key)
+            .is_some_and(|checked| checked.elapsed() < NO_POOL_RECHECK)
+        {
+            return None;
+        }
+        // Errors are not cached, only a search that found nothing
+        match pricing::find_raydium_pool(rpc_client, mint).await.ok()? {
+            Some(pool) => {
+                self.no_pool.remove(&key);
+                self.pools.insert(key, pool.clone());
+                Some(pool)
+            }
+            None => {
+                self.no_pool.insert(key, Instant::now());
+                None
+            }
+        }
+    }
+
+    async fn symbol(&mut self, rpc_client: &RpcClient, mint: &Pubkey) -> String {
+        let key = mint.to_string();
+        if let Some(symbol) = self.symbols.get(&key) {
+            return symbol.clone();
+        }
+        match get_metadata_symbol(rpc_client, mint).await {
+            Ok(symbol) => {
+                self.symbols.insert(key, symbol.clone());
+                symbol
+            }
+            Err(_) => key[..6.min(key.len())].to_string(),
+        }
+    }
+}
+
+async fn get_bonding_curves(
+    rpc_client: &RpcClient,
+    mints: &[Pubkey],
+) -> Result<Vec<Option<BondingCurve>>, Box<dyn Error + Send + Sync>> {
+    let mut curves = vec![];
+    for chunk in mints.chunks(100) {
+        let addresses: Vec<Pubkey> = chunk.iter().map(pricing::bonding_curve_address).collect();
+        let accounts = rpc_client.get_multiple_accounts(&addresses).await?;
+        curves.extend(
+            accounts
+                .into_iter()
+                .map(|account| account.and_then(|a| BondingCurve::from_account_data(&a.data).ok())),
+        );
+    }
+    Ok(curves)
+}
+
+// Read the symbol from the Metaplex metadata account of a mint
+async fn get_metadata_symbol(
+    rpc_client: &RpcClient,
+    mint: &Pubkey,
+) -> Result<String, Box<dyn Error + Send + Sync>> {
+    let program_id = Pubkey::from_str(METADATA_PROGRAM_ID)?;
+    let (address, _) = Pubkey::find_program_address(
+        &[b"metadata", program_id.as_ref(), mint.as_ref()],
+        &program_id,
+    );
+    let data = rpc_client.get_account_data(&address).await?;
+
+    // key (1), update authority (32), mint (32), then borsh strings for name and symbol
+    let read_string = |offset: usize| -> Option<(String, usize)> {
+        let len_bytes: [u8; 4] = data.get(offset..offset + 4)?.try_into().ok()?;
+        let len = u32::from_le_bytes(len_bytes) as usize;
+        let bytes = data.get(offset + 4..offset + 4 + len)?;
+        let value = String::from_utf8_lossy(bytes)
+            .trim_matches(char::from(0))
+            .trim()
+            .to_string();
+        Some((value, offset + 4 + len))
+    };
+    let (_, symbol_offset) = read_string(65).ok_or("Invalid metadata name")?;
+    let (symbol, _) = read_string(symbol_offset).ok_or("Invalid metadata symbol")?;
+
+    Ok(symbol)
+}
@@ -0,0 +1,263 @@
+use serde_json::Value;
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig};
+use solana_client::rpc_filter::{Memcmp, RpcFilterType};
+use solana_sdk::pubkey::Pubkey;
+use std::{error::Error, str::FromStr};
+
+use crate::market::Market;
+
+pub const PUMPFUN_PROGRAM_ID: &str = "6EF8rrecthR5Dkzon8Nwu78hRvfCKubJ14M5uBEwF6P";
+pub const RAYDIUM_AMM_PROGRAM_ID: &str = "675kPX9MHTjS2zt1qfr1NYHuzeLXfQM9H24wFSUt1Mp8";
+pub const WSOL_MINT: &str = "So11111111111111111111111111111111111111112";
+
+const PUMPFUN_FEE_BPS: u128 = 100;
+const PUMPFUN_TOKEN_DECIMALS: u8 = 6;
+const LAMPORTS_PER_SOL: f64 = 1_000_000_000.0;
+
+// State of a pump.fun bonding curve account
+#[derive(Debug, Clone)]
+pub struct BondingCurve {
+    pub virtual_token_reserves: u64,
+    pub virtual_sol_reserves: u64,
+    pub real_token_reserves: u64,
+    pub complete: bool,
+}
+
+impl BondingCurve {
+    pub fn from_account_data(data: &[u8]) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        // 8 byte anchor discriminator, 5 u64 reserve/supply fields and the `complete` flag
+        if data.len() < 49 {
+            return Err("Bonding curve account data too short".into());
+        }
+        let read_u64 = |offset: usize| {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(&data[offset..offset + 8]);
+            u64::from_le_bytes(bytes)
+        };
+
+        Ok(Self {
+            virtual_token_reserves: read_u64(8),
+            virtual_sol_reserves: read_u64(16),
+            real_token_reserves: read_u64(24),
+            complete: data[48] != 0,
+        })
+    }
+
+    // Spot price in SOL per whole token
+    pub fn price(&self) -> f64 {
+        if self.virtual_token_reserves == 0 {
+            return 0.0;
+        }
+        (self.virtual_sol_reserves as f64 / LAMPORTS_PER_SOL)
+            / (self.virtual_token_reserves as f64 / 10f64.powi(PUMPFUN_TOKEN_DECIMALS as i32))
+    }
+
+    // Raw token amount received for `lamports` SOL, after the pump.fun fee
+    pub fn buy_quote(&self, lamports: u64) -> u64 {
+        if self.virtual_sol_reserves == 0 || self.virtual_token_reserves == 0 {
+            return 0;
+        }
+        let sol_in = lamports as u128 * (10_000 - PUMPFUN_FEE_BPS) / 10_000;
+        let vsr = self.virtual_sol_reserves as u128;
+        let vtr = self.virtual_token_reserves as u128;
+        let tokens_out = vtr - (vsr * vtr) / (vsr + sol_in);
+
+        tokens_out.min(self.real_token_reserves as u128) as u64
+    }
+
+    // Lamports received for selling `tokens` raw token units, after the pump.fun fee
+    pub fn sell_quote(&self, tokens: u64) -> u64 {
+        if self.virtual_sol_reserves == 0 || self.virtual_token_reserves == 0 {
+            return 0;
+        }
+        let vsr = self.virtual_sol_reserves as u128;
+        let vtr = self.virtual_token_reserves as u128;
+        let sol_out = (tokens as u128 * vsr) / (vtr + tokens as u128);
+
+        (sol_out * (10_000 - PUMPFUN_FEE_BPS) / 10_000) as u64
+    }
+}
+
+pub fn bonding_curve_address(mint: &Pubkey) -> Pubkey {
+    let program_id = Pubkey::from_str(PUMPFUN_PROGRAM_ID).unwrap();
+    Pubkey::find_program_address(&[b"bonding-curve", mint.as_ref()], &program_id).0
+}
+
+pub async fn get_bonding_curve(
+    rpc_client: &RpcClient,
+    mint: &Pubkey,
+) -> Result<BondingCurve, Box<dyn Error + Send + Sync>> {
+    let data = rpc_client
+        .get_account_data(&bonding_curve_address(mint))
+        .await?;
+    BondingCurve::from_account_data(&data)
+}
+
+// The fields of a Raydium AMM v4 pool account we care about
+#[derive(Debug, Clone)]
+pub struct RaydiumPool {
+    pub base_vault: Pubkey,
+    pub quote_vault: Pubkey,
+    pub base_mint: Pubkey,
+    pub quote_mint: Pubkey,
+    pub lp_mint: Pubkey,
+    pub lp_reserve: u64,
+}
+
+impl RaydiumPool {
+    const LEN: usize = 752;
+
+    pub fn from_account_data(data: &[u8]) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        if data.len() < Self::LEN {
+            return Err("Raydium pool account data too short".into());
+        }
+        let read_pubkey = |offset: usize| Pubkey::try_from(&data[offset..offset + 32]).unwrap();
+        let mut lp_reserve = [0u8; 8];
+        lp_reserve.copy_from_slice(&data[720..728]);
+
+        Ok(Self {
+            base_vault: read_pubkey(336),
+            quote_vault: read_pubkey(368),
+            base_mint: read_pubkey(400),
+            quote_mint: read_pubkey(432),
+            lp_mint: read_pubkey(464),
+            lp_reserve: u64::from_le_bytes(lp_reserve),
+        })
+    }
+
+    // Spot price in SOL per whole token, from the vault reserves
+    pub async fn price(
+        &self,
+        rpc_client: &RpcClient,
+        mint: &Pubkey,
+    ) -> Result<f64, Box<dyn Error + Send + Sync>> {
+        let (token_vault, sol_vault) = if &self.base_mint == mint {
+            (self.base_vault, self.quote_vault)
+        } else {
+            (self.quote_vault, self.base_vault)
+        };
+        let tokens = rpc_client
+            .get_token_account_balance(&token_vault)
+            .await?
+            .ui_amount
+            .unwrap_or(0.0);
+        let sol = rpc_client
+            .get_token_account_balance(&sol_vault)
+            .await?
+            .ui_amount
+            .unwrap_or(0.0);
+
+        if tokens <= 0.0 {
+            return Ok(0.0);
+        }
+        Ok(sol / tokens)
+    }
+}
+
+// Find the Raydium AMM v4 pool pairing `mint` with SOL, on either side of the pool
+pub async fn find_raydium_pool(
+    rpc_client: &RpcClient,
+    mint: &Pubkey,
+) -> Result<Option<RaydiumPool>, Box<dyn Error + Send + Sync>> {
+    let program_id = Pubkey::from_str(RAYDIUM_AMM_PROGRAM_ID)?;
+    let wsol = Pubkey::from_str(WSOL_MINT)?;
+
+    for (mint_offset, sol_offset) in [(400, 432), (432, 400)] {
+        let config = RpcProgramAccountsConfig {
+            filters: Some(vec![
+                RpcFilterType::DataSize(RaydiumPool::LEN as u64),
+                RpcFilterType::Memcmp(Memcmp::new_base58_encoded(mint_offset, &mint.to_bytes())),
+                RpcFilterType::Memcmp(Memcmp::new_base58_encoded(sol_offset, &wsol.to_bytes())),
+            ]),
+            account_config: RpcAccountInfoConfig {
+                encoding: Some(UiAccountEncoding::Base64),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let accounts = rpc_client
+            .get_program_accounts_with_config(&program_id, config)
+            .await?;
+        if let Some((_, account)) = accounts.into_iter().next() {
+            return Ok(Some(RaydiumPool::from_account_data(&account.data)?));
+        }
+    }
+
+    Ok(None)
+}
+
+async fn jupiter_quote(
+    input_mint: &str,
+    output_mint: &str,
+    amount: u64,
+) -> Result<u64, Box<dyn Error + Send + Sync>> {
+    let response = reqwest::Client::builder()
+        .build()?
+        .get(format!(
+            "https://quote-api.jup.ag/v6/quote?inputMint={input_mint}&outputMint={output_mint}&amount={amount}&slippageBps=50"
+        ))
+        .header("Accept", "application/json")
+        .send()
+        .await?
+        .error_for_status()?;
+
+    let data: Value = response.json().await?;
+    let out_amount = data["outAmount"]
+        .as_str()
+        .ok_or("Jupiter quote has no outAmount")?
+        .parse::<u64>()?;
+
+    Ok(out_amount)
+}
+
+async fn token_decimals(
+    rpc_client: &RpcClient,
+    mint: &Pubkey,
+) -> Result<u8, Box<dyn Error + Send + Sync>> {
+    Ok(rpc_client.get_token_supply(mint).await?.decimals)
+}
+
+// Quote how many tokens (UI amount) `sol_amount` SOL buys on the given market
+pub async fn quote_buy(
+    rpc_client: &RpcClient,
+    market: Market,
+    mint: &Pubkey,
+    sol_amount: f64,
+) -> Result<f64, Box<dyn Error + Send + Sync>> {
+    let lamports = (sol_amount * LAMPORTS_PER_SOL) as u64;
+
+    if market.is_bonding_curve() {
+        let curve = get_bonding_curve(rpc_client, mint).await?;
+        if !curve.complete {
+            let tokens = curve.buy_quote(lamports);
+            return Ok(tokens as f64 / 10f64.powi(PUMPFUN_TOKEN_DECIMALS as i32));
+        }
+    }
+
+    let decimals = token_decimals(rpc_client, mint).await?;
+    let tokens = jupiter_quote(WSOL_MINT, &mint.to_string(), lamports).await?;
+    Ok(tokens as f64 / 10f64.powi(decimals as i32))
+}
+
+// Quote how much SOL selling `token_amount` tokens (UI amount) returns on the given market
+pub async fn quote_sell(
+    rpc_client: &RpcClient,
+    market: Market,
+    mint: &Pubkey,
+    token_amount: f64,
+) -> Result<f64, Box<dyn Error + Send + Sync>> {
+    if market.is_bonding_curve() {
+        let curve = get_bonding_curve(rpc_client, mint).await?;
+        if !curve.complete {
+            let tokens = (token_amount * 10f64.powi(PUMPFUN_TOKEN_DECIMALS as i32)) as u64;
+            return Ok(curve.sell_quote(tokens) as f64 / LAMPORTS_PER_SOL);
+        }
+    }
+
+    let decimals = token_decimals(rpc_client, mint).await?;
+    let tokens = (token_amount * 10f64.powi(decimals as i32)) as u64;
+    let lamports = jupiter_quote(&mint.to_string(), WSOL_MINT, tokens).await?;
+    Ok(lamports as f64 / LAMPORTS_PER_SOL)
+}
@@ -0,0 +1,291 @@
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_client::GetConfirmedSignaturesForAddress2Config;
+use solana_sdk::{program_pack::Pack, pubkey::Pubkey};
+use spl_associated_token_account::get_associated_token_address;
+use std::{error::Error, str::FromStr};
+
+use crate::config::CheckAction;
+use crate::{
+    market::Market,
+    pricing::{self, RaydiumPool},
+    State,
+};
+
+// Pages of 1000 signatures scanned before a mint is assumed to be old enough
+const MAX_SIGNATURE_PAGES: usize = 5;
+
+type CheckResult = Result<Option<String>, Box<dyn Error + Send + Sync>>;
+
+// Failed checks, split by the action configured for them
+#[derive(Debug, Default)]
+pub struct SafetyReport {
+    pub blocked: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+impl SafetyReport {
+    fn record(&mut self, action: CheckAction, result: CheckResult) {
+        let failure = match result {
+            Ok(None) => return,
+            Ok(Some(reason)) => reason,
+            Err(e) => format!("check failed: {e}"),
+        };
+        match action {
+            CheckAction::Block => self.blocked.push(failure),
+            CheckAction::Warn => self.warnings.push(failure),
+            CheckAction::Ignore => {}
+        }
+    }
+}
+
+// Run the configured on-chain checks against a mint before buying it
+pub async fn check_token(state: &State, token: &str, market: Market) -> SafetyReport {
+    let config = &state.config.safety;
+    let rpc_client = &state.rpc_client;
+    let mut report = SafetyReport::default();
+
+    let mint = match Pubkey::from_str(token) {
+        Ok(mint) => mint,
+        Err(e) => {
+            report.blocked.push(format!("invalid mint: {e}"));
+            return report;
+        }
+    };
+
+    if config.mint_authority != CheckAction::Ignore
+        || config.freeze_authority != CheckAction::Ignore
+    {
+        match get_mint(rpc_client, &mint).await {
+            Ok(mint_state) => {
+                report.record(
+                    config.mint_authority,
+                    Ok(mint_state
+                        .mint_authority
+                        .is_some()
+                        .then(|| "mint authority is set".to_string())),
+                );
+                report.record(
+                    config.freeze_authority,
+                    Ok(mint_state
+                        .freeze_authority
+                        .is_some()
+                        .then(|| "freeze authority is set".to_string())),
+                );
+            }
+            Err(e) => {
+                let e = e.to_string();
+                report.record(config.mint_authority, Err(e.clone().into()));
+                report.record(config.freeze_authority, Err(e.into()));
+            }
+        }
+    }
+
+    // Both pool checks share one search, it scans every Raydium pool account
+    let pool_needed = market.is_raydium_amm()
+        && (config.holder_concentration != CheckAction::Ignore
+            || config.lp_burned != CheckAction::Ignore);
+    let pool = if pool_needed {
+        pricing::find_raydium_pool(rpc_client, &mint)
+            .await
+            .map_err(|e| e.to_string())
+    } else {
+        Ok(None)
+    };
+
+    if config.holder_concentration != CheckAction::Ignore {
+        let result = match &pool {
+            Ok(pool) => {
+                check_holder_concentration(
+                    rpc_client,
+                    &mint,
+                    pool.as_ref(),
+                    config.top_holders,
+                    config.max_top_holders_pct,
+                )
+                .await
+            }
+            Err(e) => Err(e.clone().into()),
+        };
+        report.record(config.holder_concentration, result);
+    }
+
+    if config.token_age != CheckAction::Ignore {
+        let result = check_token_age(rpc_client, &mint, config.min_token_age_secs).await;
+        report.record(config.token_age, result);
+    }
+
+    if config.lp_burned != CheckAction::Ignore && market.is_raydium_amm() {
+        let result = match pool {
+            Ok(Some(pool)) => check_lp_burned(rpc_client, &pool, config.min_lp_burned_pct).await,
+            Ok(None) => Ok(Some("no Raydium pool found".into())),
+            Err(e) => Err(e.into()),
+        };
+        report.record(config.lp_burned, result);
+    }
+
+    report
+}
+
+async fn get_mint(
+    rpc_client: &RpcClient,
+    mint: &Pubkey,
+) -> Result<spl_token::state::Mint, Box<dyn Error + Send + Sync>> {
+    let data = rpc_client.get_account_data(mint).await?;
+    // Token-2022 mints share the base layout, extensions follow it
+    if data.len() < spl_token::state::Mint::LEN {
+        return Err("account is not a mint".into());
+    }
+    Ok(spl_token::state::Mint::unpack_from_slice(
+        &data[..spl_token::state::Mint::LEN],
+    )?)
+}
+
+async fn check_holder_concentration(
+    rpc_client: &RpcClient,
+    mint: &Pubkey,
+    pool: Option<&RaydiumPool>,
+    top_holders: usize,
+    max_pct: f64,
+) -> CheckResult {
+    let supply = rpc_client
+        .get_token_supply(mint)
+        .await?
+        .amount
+        .parse::<u64>()?;
+    if supply == 0 {
+        return Ok(Some("token has no supply".into()));
+    }
+
+    // Liquidity held by the bonding curve or the pool is not a holder
+    let mut excluded = vec![get_associated_token_address(
+        &pricing::bonding_curve_address(mint),
+        mint,
+    )];
+    if let Some(pool) = pool {
+        excluded.push(pool.base_vault);
+        excluded.push(pool.quote_vault);
+    }
+    let excluded: Vec<String> = excluded.iter().map(|k| k.to_string()).collect();
+
+    let held: u64 = rpc_client
+        .get_token_largest_accounts(mint)
+        .await?
+        .into_iter()
+        .filter(|account| !excluded.contains(&account.address))
+        .take(top_holders)
+        .filter_map(|account| account.amount.amount.parse::<u64>().ok())
+        .sum();
+
+    Ok(concentration_failure(held, supply, top_holders, max_pct))
+}
+
+fn concentration_failure(
+    held: u64,
+    supply: u64,
+    top_holders: usize,
+    max_pct: f64,
+) -> Option<String> {
+    let pct = held as f64 / supply as f64 * 100.0;
+    (pct > max_pct).then(|| format!("top {top_holders} holders own {pct:.1}% (max {max_pct}%)"))
+}
+
+async fn check_token_age(rpc_client: &RpcClient, mint: &Pubkey, min_age_secs: u64) -> CheckResult {
+    let mut before = None;
+    let mut oldest = None;
+
+    for _ in 0..MAX_SIGNATURE_PAGES {
+        let page = rpc_client
+            .get_signatures_for_address_with_config(
+                mint,
+                GetConfirmedSignaturesForAddress2Config {
+                    before,
+                    limit: Some(1000),
+                    ..Default::default()
+                },
+            )
+            .await?;
+        let Some(last) = page.last() else {
+            break;
+        };
+        oldest = last.block_time.or(oldest);
+        if page.len() < 1000 {
+            let age = chrono::Utc::now().timestamp() - oldest.unwrap_or_default();
+            if age < min_age_secs as i64 {
+                return Ok(Some(format!("token is {age}s old (min {min_age_secs}s)")));
+            }
+            return Ok(None);
+        }
+        before = Some(last.signature.parse()?);
+    }
+
+    // More history than we scan, the mint is old
+    Ok(None)
+}
+
+// Only burned LP counts. LP held by a locker program can still be withdrawn once the
+// lock ends, so it fails the check like LP in a wallet would.
+async fn check_lp_burned(rpc_client: &RpcClient, pool: &RaydiumPool, min_pct: f64) -> CheckResult {
+    if pool.lp_reserve == 0 {
+        return Ok(Some("pool has no LP reserve".into()));
+    }
+
+    // Burned LP leaves the mint supply but stays counted in the pool's LP reserve
+    let lp_supply = rpc_client
+        .get_token_supply(&pool.lp_mint)
+        .await?
+        .amount
+        .parse::<u64>()?;
+    Ok(lp_burned_failure(lp_supply, pool.lp_reserve, min_pct))
+}
+
+fn lp_burned_failure(lp_supply: u64, lp_reserve: u64, min_pct: f64) -> Option<String> {
+    let burned = (1.0 - lp_supply as f64 / lp_reserve as f64).max(0.0) * 100.0;
+    (burned < min_pct).then(|| format!("only {burned:.1}% of LP burned (min {min_pct}%)"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn holder_concentration() {
+        assert_eq!(concentration_failure(0, 1_000, 10, 30.0), None);
+        assert_eq!(concentration_failure(300, 1_000, 10, 30.0), None);
+        assert_eq!(
+            concentration_failure(301, 1_000, 10, 30.0).as_deref(),
+            Some("top 10 holders own 30.1% (max 30%)")
+        );
+        assert!(concentration_failure(1_000, 1_000, 1, 99.9).is_some());
+    }
+
+    #[test]
+    fn lp_burned() {
+        // Nothing left outside the pool's reserve
+        assert_eq!(lp_burned_failure(0, 1_000, 90.0), None);
+        assert_eq!(lp_burned_failure(100, 1_000, 90.0), None);
+        assert_eq!(
+            lp_burned_failure(101, 1_000, 90.0).as_deref(),
+            Some("only 89.9% of LP burned (min 90%)")
+        );
+        assert_eq!(
+            lp_burned_failure(1_000, 1_000, 90.0).as_deref(),
+            Some("only 0.0% of LP burned (min 90%)")
+        );
+        // Supply above the reserve is clamped rather than negative
+        assert_eq!(
+            lp_burned_failure(2_000, 1_000, 1.0).as_deref(),
+            Some("only 0.0% of LP burned (min 1%)")
+        );
+    }
+
+    #[test]
+    fn reports_by_action() {
+        let mut report = SafetyReport::default();
+        report.record(CheckAction::Block, Ok(Some("blocked".into())));
+        report.record(CheckAction::Warn, Err("rpc down".into()));
+        report.record(CheckAction::Ignore, Ok(Some("ignored".into())));
+        report.record(CheckAction::Block, Ok(None));
+        assert_eq!(report.blocked, ["blocked"]);
+        assert_eq!(report.warnings, ["check failed: rpc down"]);
+    }
+}
@@ -0,0 +1,284 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    error::Error,
+    sync::{mpsc::Sender, Arc, Mutex},
+    time::Duration,
+};
+
+use futures::future::BoxFuture;
+use regex::Regex;
+use tokio::{
+    sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+    time::{sleep, Instant},
+};
+
+use crate::{filters, tasks, ui::UserInfo, State};
+
+// A post from a watched author, as emitted by any source
+#[derive(Debug, Clone)]
+pub struct Signal {
+    // Name of the source that produced it, e.g. "twitter"
+    pub source: &'static str,
+    // Id of the post at the source, used to drop duplicates across sources
+    pub id: String,
+    pub author: String,
+    pub text: String,
+    pub urls: Vec<String>,
+    // Unix seconds the post was created, or received when the source has no timestamp
+    pub timestamp: i64,
+    pub kind: TweetKind,
+    pub quoted_text: Option<String>,
+    // Cashtags without the `$`
+    pub cashtags: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TweetKind {
+    Original,
+    Reply,
+    Retweet,
+    Quote,
+}
+
+impl std::fmt::Display for TweetKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let kind = match self {
+            TweetKind::Original => "tweet",
+            TweetKind::Reply => "reply",
+            TweetKind::Retweet => "retweet",
+            TweetKind::Quote => "quote",
+        };
+        write!(f, "{kind}")
+    }
+}
+
+impl Signal {
+    pub fn new(source: &'static str, id: String, author: String, text: String) -> Self {
+        let url_regex = Regex::new(r"https?://[^\s]+").unwrap();
+        let urls = url_regex
+            .find_iter(&text)
+            .map(|m| m.as_str().to_string())
+            .collect();
+
+        Self {
+            source,
+            id,
+            author,
+            text,
+            urls,
+            timestamp: chrono::Utc::now().timestamp(),
+            kind: TweetKind::Original,
+            quoted_text: None,
+            cashtags: vec![],
+        }
+    }
+}
+
+// A new status for a user's row, from a trade still landing after its signal was handled
+#[derive(Debug, Clone)]
+pub struct StatusUpdate {
+    pub user: String,
+    pub status: String,
+}
+
+// Anything that can watch authors and report their posts
+pub trait SignalSource: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    // Runs until the source fails, sending every new post on `signals`. Called again
+    // by the supervisor after a failure, so every run has to start from scratch.
+    fn run(
+        &self,
+        signals: UnboundedSender<Signal>,
+        health: HealthReporter,
+    ) -> BoxFuture<'static, Result<(), Box<dyn Error + Send + Sync>>>;
+}
+
+// Restart delays of a failed source, doubled after every failure
+const INITIAL_BACKOFF: Duration = Duration::from_secs(2);
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+// A run lasting this long counts as healthy and resets the backoff
+const HEALTHY_RUN: Duration = Duration::from_secs(120);
+
+#[derive(Debug, Clone)]
+pub enum SourceStatus {
+    Starting,
+    Running,
+    Reconnecting { error: String, retry_at: i64 },
+}
+
+#[derive(Debug, Clone)]
+pub struct SourceHealth {
+    pub status: SourceStatus,
+    // Unix seconds of the last successful poll
+    pub last_poll: Option<i64>,
+}
+
+// Health of every source by name, shown in the TUI
+pub type MonitorHealth = Arc<Mutex<BTreeMap<&'static str, SourceHealth>>>;
+
+// Handed to a source so it can report successful polls
+#[derive(Clone)]
+pub struct HealthReporter {
+    source: &'static str,
+    health: MonitorHealth,
+}
+
+impl HealthReporter {
+    pub fn polled(&self) {
+        self.update(|health| {
+            health.status = SourceStatus::Running;
+            health.last_poll = Some(chrono::Utc::now().timestamp());
+        });
+    }
+
+    fn update(&self, f: impl FnOnce(&mut SourceHealth)) {
+        let mut health = self.health.lock().unwrap();
+        let entry = health.entry(self.source).or_insert(SourceHealth {
+            status: SourceStatus::Starting,
+            last_poll: None,
+        });
+        f(entry);
+    }
+}
+
+// Start every source and dispatch their signals until all of them have stopped
+pub async fn run(
+    sources: Vec<Box<dyn SignalSource>>,
+    status_updates: UnboundedReceiver<StatusUpdate>,
+    tx: Sender<Vec<UserInfo>>,
+    state: State,
+) {
+    let (signal_tx, signal_rx) = unbounded_channel();
+
+    for source in sources {
+        tokio::spawn(supervise(
+            source,
+            signal_tx.clone(),
+            state.monitor_health.clone(),
+        ));
+    }
+    drop(signal_tx);
+
+    dispatch(signal_rx, status_updates, tx, state).await;
+}
+
+// Keep a source running, restarting it with exponential backoff when it fails
+async fn supervise(
+    source: Box<dyn SignalSource>,
+    signals: UnboundedSender<Signal>,
+    health: MonitorHealth,
+) {
+    let name = source.name();
+    let reporter = HealthReporter {
+        source: name,
+        health,
+    };
+    let mut backoff = INITIAL_BACKOFF;
+
+    loop {
+        reporter.update(|health| health.status = SourceStatus::Starting);
+        log::info!(target:"app", "Starting {name} source");
+
+        let started = Instant::now();
+        let error = match source.run(signals.clone(), reporter.clone()).await {
+            Ok(()) => "stopped".to_string(),
+            Err(e) => e.to_string(),
+        };
+        if signals.is_closed() {
+            return;
+        }
+        if started.elapsed() >= HEALTHY_RUN {
+            backoff = INITIAL_BACKOFF;
+        }
+
+        log::error!(target:"app", "{name} source down: {error}, restarting in {}s", backoff.as_secs());
+        tracing::error!("{name} source down: {error}");
+        reporter.update(|health| {
+            health.status = SourceStatus::Reconnecting {
+                error,
+                retry_at: chrono::Utc::now().timestamp() + backoff.as_secs() as i64,
+            }
+        });
+
+        sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+// Run detection and trading for each signal and keep the users table up to date
+async fn dispatch(
+    mut signals: UnboundedReceiver<Signal>,
+    mut status_updates: UnboundedReceiver<StatusUpdate>,
+    tx: Sender<Vec<UserInfo>>,
+    state: State,
+) {
+    let mut user_info_map: HashMap<String, UserInfo> = HashMap::new();
+    for u in state.config.users.iter().map(|u| u.name()) {
+        user_info_map.insert(
+            u.to_lowercase(),
+            UserInfo {
+                username: u.to_string(),
+                last_tweet: "".into(),
+                status: "Waiting for Tweet".into(),
+            },
+        );
+    }
+    tx.send(user_info_map.values().cloned().collect()).unwrap();
+
+    let mut seen: HashSet<String> = HashSet::new();
+    loop {
+        let signal = tokio::select! {
+            Some(update) = status_updates.recv() => {
+                if let Some(user) = user_info_map.get_mut(&update.user.to_lowercase()) {
+                    user.status = update.status;
+                    tx.send(user_info_map.values().cloned().collect()).unwrap();
+                }
+                continue;
+            }
+            signal = signals.recv() => match signal {
+                Some(signal) => signal,
+                None => break,
+            },
+        };
+        if !seen.insert(signal.id.clone()) {
+            continue;
+        }
+        let Some(user) = user_info_map.get_mut(&signal.author.to_lowercase()) else {
+            continue;
+        };
+
+        tracing::info!(
+            "New {} {} from {} ({}s old): {} | quoted: {} | cashtags: {:?} | urls: {:?}",
+            signal.source,
+            signal.kind,
+            signal.author,
+            chrono::Utc::now().timestamp() - signal.timestamp,
+            signal.text,
+            signal.quoted_text.as_deref().unwrap_or("-"),
+            signal.cashtags,
+            signal.urls
+        );
+
+        let status = match filters::check(&state.config.filter_for(&signal.author), &signal) {
+            Err(rule) => {
+                tracing::info!(
+                    "Filtered signal {} from {}: {rule}",
+                    signal.id,
+                    signal.author
+                );
+                format!("Filtered: {rule}")
+            }
+            Ok(()) => match tasks::start_user_tasks(signal.clone(), state.clone()).await {
+                Ok(status) => status,
+                Err(e) => {
+                    tracing::error!("Error handling signal {}: {e}", signal.id);
+                    "Error Occurred... Waiting for new Tweet".into()
+                }
+            },
+        };
+        user.last_tweet = signal.text;
+        user.status = status;
+        tx.send(user_info_map.values().cloned().collect()).unwrap();
+    }
+}
@@ -0,0 +1,435 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+
+use crate::config::SellConfig;
+
+// Seconds after an exit finished before it is allowed to fire again, for balances to catch up
+const EXIT_RETRY_SECS: i64 = 60;
+
+// Per position exit state, persisted so levels do not fire twice across restarts
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ExitState {
+    pub initial_tokens: f64,
+    pub peak_price: f64,
+    pub fired_levels: Vec<usize>,
+    pub closing_since: Option<i64>,
+    // Take-profit levels of the exit in flight, fired again if it does not sell
+    #[serde(default)]
+    pub pending_levels: Vec<usize>,
+    // Tokens held when the exit in flight was ordered
+    #[serde(default)]
+    pub closing_tokens: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct ExitOrder {
+    pub wallet: String,
+    pub mint: String,
+    pub tokens: f64,
+    pub reason: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ExitTracker {
+    #[serde(skip)]
+    path: String,
+    // Keyed by `position_key`
+    pub positions: HashMap<String, ExitState>,
+    // Positions whose exit is still being sent or retried
+    #[serde(skip)]
+    in_flight: HashSet<String>,
+}
+
+// Positions are per wallet, the same mint can be held by several wallets
+pub fn position_key(wallet: &str, mint: &str) -> String {
+    format!("{wallet}/{mint}")
+}
+
+impl ExitTracker {
+    // State saved before wallets existed is keyed by mint alone and belongs to the default wallet
+    pub fn load_or_create(path: &str, default_wallet: &str) -> Self {
+        let mut tracker: ExitTracker = fs::read_to_string(path)
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default();
+        tracker.path = path.to_string();
+        tracker.positions = tracker
+            .positions
+            .into_iter()
+            .map(|(key, state)| {
+                if key.contains('/') {
+                    (key, state)
+                } else {
+                    (position_key(default_wallet, &key), state)
+                }
+            })
+            .collect();
+        tracker
+    }
+
+    fn save(&self) {
+        match serde_json::to_string_pretty(self) {
+            Ok(data) => {
+                if let Err(e) = fs::write(&self.path, data) {
+                    tracing::error!("Unable to save exit state: {e}");
+                }
+            }
+            Err(e) => tracing::error!("Unable to serialize exit state: {e}"),
+        }
+    }
+
+    // Drop the state of positions that are no longer held, `held` holds position keys
+    pub fn retain(&mut self, held: &[String]) {
+        let before = self.positions.len();
+        self.positions.retain(|key, _| held.contains(key));
+        if self.positions.len() != before {
+            self.save();
+        }
+    }
+
+    // Decide whether a position should be (partially) sold. Take-profit fractions
+    // are relative to the largest size seen before the first level fired. The trailing
+    // stop follows the peak token price and is armed once every take-profit level has
+    // fired, or once the position has been in profit when no ladder is configured.
+    pub fn evaluate(
+        &mut self,
+        config: &SellConfig,
+        wallet: &str,
+        mint: &str,
+        tokens: f64,
+        cost_basis: f64,
+        current_value: f64,
+    ) -> Option<ExitOrder> {
+        let key = position_key(wallet, mint);
+        let before = self.positions.get(&key).cloned();
+        let order = self.decide(config, wallet, mint, tokens, cost_basis, current_value);
+        // Most ticks change nothing, the file is only written when one does
+        if self.positions.get(&key) != before.as_ref() {
+            self.save();
+        }
+        order
+    }
+
+    fn decide(
+        &mut self,
+        config: &SellConfig,
+        wallet: &str,
+        mint: &str,
+        tokens: f64,
+        cost_basis: f64,
+        current_value: f64,
+    ) -> Option<ExitOrder> {
+        // A zero value means the position could not be priced, not that it is worthless
+        if tokens <= 0.0 || cost_basis <= 0.0 || current_value <= 0.0 {
+            return None;
+        }
+
+        let now = chrono::Utc::now().timestamp();
+        let price = current_value / tokens;
+        let pnl = ((current_value / cost_basis) - 1.0) * 100.0;
+        let levels = config.take_profit_levels();
+        let key = position_key(wallet, mint);
+        let in_flight = self.in_flight.contains(&key);
+
+        let state = self.positions.entry(key.clone()).or_default();
+        if state.fired_levels.is_empty() {
+            state.initial_tokens = state.initial_tokens.max(tokens);
+        }
+        state.peak_price = state.peak_price.max(price);
+
+        if let Some(since) = state.closing_since {
+            // The sell is retried for as long as its retry policy allows
+            if in_flight || now - since < EXIT_RETRY_SECS {
+                return None;
+            }
+            state.closing_since = None;
+            // Nothing was sold, so the take-profit levels of that exit have to fire again
+            if tokens >= state.closing_tokens {
+                let pending = std::mem::take(&mut state.pending_levels);
+                state.fired_levels.retain(|level| !pending.contains(level));
+            }
+            state.pending_levels.clear();
+        }
+
+        let mut order = None;
+
+        if let Some(stop_loss) = config.stop_loss {
+            if pnl <= -stop_loss {
+                order = Some((tokens, format!("stop-loss at {pnl:.2}%")));
+            }
+        }
+
+        if order.is_none() {
+            let mut to_sell = 0.0;
+            let mut hit = vec![];
+            for (i, level) in levels.iter().enumerate() {
+                if !state.fired_levels.contains(&i) && pnl >= level.at {
+                    to_sell += state.initial_tokens * level.fraction;
+                    hit.push(i);
+                }
+            }
+            if !hit.is_empty() {
+                state.fired_levels.extend(&hit);
+                state.pending_levels = hit;
+                order = Some((to_sell.min(tokens), format!("take-profit at {pnl:.2}%")));
+            }
+        }
+
+        if order.is_none() {
+            if let Some(trailing_stop) = config.trailing_stop {
+                let armed = if config.take_profit.is_empty() {
+                    state.peak_price * tokens > cost_basis
+                } else {
+                    state.fired_levels.len() == levels.len()
+                };
+                let drawdown = (1.0 - price / state.peak_price) * 100.0;
+                if armed && drawdown >= trailing_stop {
+                    order = Some((tokens, format!("trailing stop {drawdown:.2}% off peak")));
+                }
+            }
+        }
+
+        order.map(|(to_sell, reason)| {
+            state.closing_since = Some(now);
+            state.closing_tokens = tokens;
+            self.in_flight.insert(key);
+            ExitOrder {
+                wallet: wallet.to_string(),
+                mint: mint.to_string(),
+                tokens: to_sell,
+                reason,
+            }
+        })
+    }
+
+    // Called once the sell of an exit order stopped retrying. A landed exit stays closing
+    // until the balance catches up, a failed one may fire again right away.
+    pub fn exit_finished(&mut self, wallet: &str, mint: &str, landed: bool) {
+        let key = position_key(wallet, mint);
+        self.in_flight.remove(&key);
+        let Some(state) = self.positions.get_mut(&key) else {
+            return;
+        };
+        if landed {
+            state.closing_since = Some(chrono::Utc::now().timestamp());
+        } else {
+            state.closing_since = None;
+            let pending = std::mem::take(&mut state.pending_levels);
+            state.fired_levels.retain(|level| !pending.contains(level));
+        }
+        self.save();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{RetryConfig, TakeProfitLevel};
+    use std::path::Path;
+
+    // A tracker saving to a file of its own, removed when the test ends
+    struct TestTracker {
+        path: String,
+        tracker: ExitTracker,
+    }
+
+    impl TestTracker {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir()
+                .join(format!("exit-{name}-{}.json", std::process::id()))
+                .to_string_lossy()
+                .into_owned();
+            let _ = fs::remove_file(&path);
+            let tracker = ExitTracker::load_or_create(&path, "wallet");
+            Self { path, tracker }
+        }
+
+        fn evaluate(
+            &mut self,
+            config: &SellConfig,
+            tokens: f64,
+            cost_basis: f64,
+            value: f64,
+        ) -> Option<ExitOrder> {
+            self.tracker
+                .evaluate(config, "wallet", "mint", tokens, cost_basis, value)
+        }
+
+        fn state(&mut self) -> &mut ExitState {
+            self.tracker
+                .positions
+                .get_mut(&position_key("wallet", "mint"))
+                .unwrap()
+        }
+
+        // Let the retry window of the last exit run out
+        fn expire_exit(&mut self) {
+            self.tracker.exit_finished("wallet", "mint", true);
+            self.state().closing_since = Some(chrono::Utc::now().timestamp() - EXIT_RETRY_SECS);
+        }
+    }
+
+    impl Drop for TestTracker {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+
+    fn config(
+        stop_loss: Option<f64>,
+        trailing_stop: Option<f64>,
+        take_profit: &[(f64, f64)],
+    ) -> SellConfig {
+        SellConfig {
+            slippage: 10.0,
+            use_jito: false,
+            jito_tip: 0.0,
+            prio_fee: 0.0001,
+            auto_sell: true,
+            sell_at: 1000.0,
+            stop_loss,
+            trailing_stop,
+            take_profit: take_profit
+                .iter()
+                .map(|&(at, fraction)| TakeProfitLevel { at, fraction })
+                .collect(),
+            retry: RetryConfig::default(),
+        }
+    }
+
+    fn ladder() -> SellConfig {
+        config(None, None, &[(100.0, 0.5), (50.0, 0.25)])
+    }
+
+    #[test]
+    fn unpriced_positions_never_exit() {
+        let mut test = TestTracker::new("unpriced");
+        let config = config(Some(10.0), None, &[]);
+        assert!(test.evaluate(&config, 1000.0, 1.0, 0.0).is_none());
+        assert!(test.evaluate(&config, 0.0, 1.0, 0.5).is_none());
+    }
+
+    #[test]
+    fn stop_loss() {
+        let mut test = TestTracker::new("stop-loss");
+        let config = config(Some(25.0), None, &[]);
+        assert!(test.evaluate(&config, 1000.0, 1.0, 0.8).is_none());
+        let order = test.evaluate(&config, 1000.0, 1.0, 0.75).unwrap();
+        assert_eq!(order.tokens, 1000.0);
+        assert!(order.reason.starts_with("stop-loss"), "{}", order.reason);
+        // Not ordered again while the sell is in flight
+        assert!(test.evaluate(&config, 1000.0, 1.0, 0.5).is_none());
+    }
+
+    #[test]
+    fn take_profit_ladder() {
+        let mut test = TestTracker::new("ladder");
+        let config = ladder();
+        assert!(test.evaluate(&config, 1000.0, 1.0, 1.4).is_none());
+
+        let order = test.evaluate(&config, 1000.0, 1.0, 1.5).unwrap();
+        assert_eq!(order.tokens, 250.0);
+        assert!(order.reason.starts_with("take-profit"), "{}", order.reason);
+
+        // The first level sold, it does not fire again
+        test.expire_exit();
+        assert!(test.evaluate(&config, 750.0, 0.75, 1.2).is_none());
+
+        // Fractions are of the initial size
+        let order = test.evaluate(&config, 750.0, 0.75, 1.5).unwrap();
+        assert_eq!(order.tokens, 500.0);
+    }
+
+    #[test]
+    fn levels_hit_together() {
+        let mut test = TestTracker::new("together");
+        let order = test.evaluate(&ladder(), 1000.0, 1.0, 2.5).unwrap();
+        assert_eq!(order.tokens, 750.0);
+    }
+
+    #[test]
+    fn failed_exit_fires_again() {
+        let mut test = TestTracker::new("failed");
+        let config = ladder();
+        assert!(test.evaluate(&config, 1000.0, 1.0, 1.5).is_some());
+        test.tracker.exit_finished("wallet", "mint", false);
+        let order = test.evaluate(&config, 1000.0, 1.0, 1.5).unwrap();
+        assert_eq!(order.tokens, 250.0);
+    }
+
+    #[test]
+    fn unsold_exit_fires_again_after_timeout() {
+        let mut test = TestTracker::new("unsold");
+        let config = ladder();
+        assert!(test.evaluate(&config, 1000.0, 1.0, 1.5).is_some());
+        // Landed, but the balance never went down
+        test.expire_exit();
+        let order = test.evaluate(&config, 1000.0, 1.0, 1.5).unwrap();
+        assert_eq!(order.tokens, 250.0);
+    }
+
+    #[test]
+    fn exits_wait_for_the_sell() {
+        let mut test = TestTracker::new("in-flight");
+        let config = ladder();
+        assert!(test.evaluate(&config, 1000.0, 1.0, 1.5).is_some());
+        // However long the sell retries, the exit stays closing
+        test.state().closing_since = Some(chrono::Utc::now().timestamp() - 10 * EXIT_RETRY_SECS);
+        assert!(test.evaluate(&config, 1000.0, 1.0, 3.0).is_none());
+        // A landed exit waits for the balance to catch up
+        test.tracker.exit_finished("wallet", "mint", true);
+        assert!(test.evaluate(&config, 1000.0, 1.0, 3.0).is_none());
+    }
+
+    #[test]
+    fn trailing_stop_after_ladder() {
+        let mut test = TestTracker::new("trailing-ladder");
+        let config = config(None, Some(20.0), &[(50.0, 0.5)]);
+        // Not armed before the ladder is done
+        assert!(test.evaluate(&config, 1000.0, 1.0, 1.4).is_none());
+        assert!(test.evaluate(&config, 1000.0, 1.0, 1.0).is_none());
+
+        assert!(test.evaluate(&config, 1000.0, 1.0, 2.0).is_some());
+        test.expire_exit();
+        // Peak price 0.002, 25% off the peak
+        let order = test.evaluate(&config, 500.0, 0.5, 0.75).unwrap();
+        assert_eq!(order.tokens, 500.0);
+        assert!(
+            order.reason.starts_with("trailing stop"),
+            "{}",
+            order.reason
+        );
+    }
+
+    #[test]
+    fn trailing_stop_without_ladder() {
+        let mut test = TestTracker::new("trailing");
+        let config = config(None, Some(20.0), &[]);
+        // Never in profit, so not armed
+        assert!(test.evaluate(&config, 1000.0, 1.0, 0.9).is_none());
+        assert!(test.evaluate(&config, 1000.0, 1.0, 0.5).is_none());
+
+        assert!(test.evaluate(&config, 1000.0, 1.0, 1.5).is_none());
+        assert!(test.evaluate(&config, 1000.0, 1.0, 1.25).is_none());
+        let order = test.evaluate(&config, 1000.0, 1.0, 1.2).unwrap();
+        assert_eq!(order.tokens, 1000.0);
+    }
+
+    #[test]
+    fn saves_only_changes() {
+        let mut test = TestTracker::new("saves");
+        let config = ladder();
+        assert!(test.evaluate(&config, 1000.0, 1.0, 1.2).is_none());
+        assert!(Path::new(&test.path).exists());
+
+        fs::remove_file(&test.path).unwrap();
+        assert!(test.evaluate(&config, 1000.0, 1.0, 1.2).is_none());
+        assert!(test.evaluate(&config, 1000.0, 1.0, 1.1).is_none());
+        assert!(!Path::new(&test.path).exists());
+
+        // A new peak is worth saving
+        assert!(test.evaluate(&config, 1000.0, 1.0, 1.3).is_none());
+        assert!(Path::new(&test.path).exists());
+    }
+}
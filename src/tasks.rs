@@ -1,192 +1,984 @@
+use log::Level;
 use regex::Regex;
-use serde_json::Value;
-use solana_sdk::pubkey::Pubkey;
-use std::{error::Error, str::FromStr};
+use solana_client::rpc_config::RpcTransactionConfig;
+use solana_sdk::{
+    account::Account,
+    commitment_config::CommitmentConfig,
+    program_pack::Pack,
+    pubkey::Pubkey,
+    signature::{Keypair, Signature},
+};
+use solana_transaction_status::{
+    option_serializer::OptionSerializer, UiTransactionEncoding, UiTransactionTokenBalance,
+};
+use std::{error::Error, str::FromStr, time::Duration};
+use tokio::time::{sleep, Instant};
 
-use crate::State;
+use crate::{
+    confirm::{self, FailureClass, Tracker, TxStage},
+    journal::{Side, TradeRecord},
+    links,
+    market::{self, Market},
+    positions::TOKEN_2022_PROGRAM_ID,
+    pricing, safety,
+    signals::{Signal, StatusUpdate},
+    State,
+};
+
+// Token-2022 accounts with extensions store their type after the base account size
+const TOKEN_2022_ACCOUNT_TYPE_OFFSET: usize = 165;
+const TOKEN_2022_MINT_TYPE: u8 = 1;
+
+// Pause before sending a failed trade again
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+// Generous bound on one attempt: sending, tracking until it lands or is given up, and
+// reading its fill
+const MAX_ATTEMPT_DURATION: Duration = Duration::from_secs(300);
+// Reads of a landed transaction, waiting twice as long after each failed one
+const FILL_ATTEMPTS: u32 = 8;
+const FILL_FIRST_DELAY: Duration = Duration::from_millis(500);
 
 pub async fn start_user_tasks(
-    tweet: String,
+    signal: Signal,
     state: State,
 ) -> Result<String, Box<dyn Error + Send + Sync>> {
-    let buy_config = tmc_solana_proto::proto::BuyConfig {
-        slippage: state.config.buy_config.slippage,
-        use_jito: state.config.buy_config.use_jito,
-        jito_tip: state.config.buy_config.jito_tip,
-        autobuy: false,
-        prio_fee: 0.0001,
-        sol_amount_left: 0.0,
-        sol_amount_right: 0.0,
-        sol_amount_autobuy: 0.0,
-    };
-    match find_solana_token_address(&tweet).await {
-        Ok(option) => match option {
+    let user = signal.author;
+    let tweet = signal.text;
+    let user_buy_config = state.config.buy_config_for(&user);
+    match find_solana_token_addresses(&state, &tweet, &signal.urls).await {
+        Ok(mints) => match mints.first().cloned() {
+            Some(_) if mints.len() > 1 => {
+                log::warn!(target:"app", "Not buying, {user} posted multiple mints: {}", mints.join(", "));
+                Ok(format!("ambiguous: multiple mints ({})", mints.join(", ")))
+            }
             Some(token) => {
-                let market_res = identify_markets(&token).await;
-
-                match market_res {
-                    Ok(market) => match market {
-                        "PumpFun" => {
-                            let status = format!("Found PumpFun Token: {token}");
-                            tokio::task::spawn(async move {
-                                state
-                                    .pumpfun_engine
-                                    .buy(
-                                        state.wallet.insecure_clone(),
-                                        Pubkey::from_str(&token).unwrap(),
-                                        state.config.buy_config.amount,
-                                        state.config.buy_config.slippage,
-                                        buy_config.clone(),
-                                    )
-                                    .await
-                                    .unwrap();
-                            });
-                            return Ok(status);
-                        }
-                        _ => {
-                            let status = format!("Found Jupiter Token: {token}");
-                            tokio::task::spawn(async move {
-                                tracing::info!("{token}");
-                                state
-                                    .jupiter_engine
-                                    .buy(
-                                        state.wallet.insecure_clone(),
-                                        Pubkey::from_str(&token).unwrap(),
-                                        state.config.buy_config.amount,
-                                        state.config.buy_config.slippage,
-                                        buy_config.clone(),
-                                    )
-                                    .await
-                                    .unwrap();
-                            });
-                            return Ok(status);
-                        }
-                    },
-                    Err(_) => {
+                if let Err(rule) = check_cooldown(&user, &state) {
+                    log::info!(target:"app", "Filtered buy of {token} from {user}: {rule}");
+                    return Ok(format!("Filtered: {rule}"));
+                }
+                if let Err(reason) = reserve_buy(&token, &user, user_buy_config.amount, &state) {
+                    log::warn!(target:"app", "Skipped buy of {token}: {reason}");
+                    return Ok(format!("Skipped {token}: {reason}"));
+                }
+
+                let market = match market::identify(&state.rpc_client, &token).await {
+                    Ok(market) => market,
+                    Err(e) => {
+                        state.buy_guard.lock().unwrap().settle(&token);
+                        tracing::error!("Unable to identify market of {token}: {e}");
                         return Ok("Error Occurred... Waiting for new Tweet".into());
                     }
+                };
+                if market == Market::Unknown && user_buy_config.skip_unknown_market {
+                    state.buy_guard.lock().unwrap().settle(&token);
+                    log::warn!(target:"app", "Skipped buy of {token}: no market found");
+                    return Ok(format!("Skipped {token}: no market found"));
                 }
+
+                let report = safety::check_token(&state, &token, market).await;
+                for warning in report.warnings.iter() {
+                    log::warn!(target:"app", "Safety warning for {token}: {warning}");
+                }
+                if !report.blocked.is_empty() {
+                    let reason = report.blocked.join(", ");
+                    state.buy_guard.lock().unwrap().settle(&token);
+                    log::error!(target:"app", "Blocked buy of {token}: {reason}");
+                    return Ok(format!("Blocked {token}: {reason}"));
+                }
+                let warnings = if report.warnings.is_empty() {
+                    String::new()
+                } else {
+                    format!(" (warn: {})", report.warnings.join(", "))
+                };
+
+                let wallet = state.wallets.for_buy(&state.config, &user);
+                let keypair = wallet.keypair.insecure_clone();
+                let wallet_name = if state.wallets.all().len() > 1 {
+                    format!(" [{}]", wallet.name)
+                } else {
+                    String::new()
+                };
+                let wallet = wallet.pubkey();
+
+                if state.config.paper_trading {
+                    let status =
+                        format!("[SIM] Found {market} Token: {token}{wallet_name}{warnings}");
+                    tokio::task::spawn(async move {
+                        paper_buy(wallet, token, market, user, tweet, state).await;
+                    });
+                    return Ok(status);
+                }
+
+                let status = format!("Found {market} Token: {token}{wallet_name}{warnings}");
+                let trade = Trade {
+                    side: Side::Buy,
+                    wallet,
+                    token,
+                    market,
+                    amount: user_buy_config.amount,
+                    user,
+                    tweet,
+                };
+                tokio::task::spawn(async move {
+                    execute_trade(trade, keypair, state).await;
+                });
+                Ok(status)
             }
-            None => {
-                return Ok("Waiting for new Tweet".into());
-            }
+            None => Ok("Waiting for new Tweet".into()),
         },
-        Err(_) => {
-            return Ok("Error Occurred... Waiting for new Tweet".into());
+        Err(_) => Ok("Error Occurred... Waiting for new Tweet".into()),
+    }
+}
+
+// Sell from the wallet holding the tokens, `wallet` is its public key. Returns once the
+// sell landed or stopped retrying, with whether it landed.
+pub async fn sell_token_task(wallet: String, token: String, amount: f64, state: State) -> bool {
+    if state.config.paper_trading {
+        return paper_sell(wallet, token, amount, state).await;
+    }
+
+    let Some(keypair) = state
+        .wallets
+        .by_pubkey(&wallet)
+        .map(|w| w.keypair.insecure_clone())
+    else {
+        log::error!(target:"app", "Unable to sell {token}: no keypair for wallet {wallet}");
+        return false;
+    };
+
+    let user = state
+        .journal
+        .lock()
+        .unwrap()
+        .wallet_position(&wallet, &token, false)
+        .map(|p| p.user)
+        .unwrap_or_default();
+
+    log::warn!(target:"app", "Selling {token}");
+
+    // The market is identified by the first attempt, so a failed lookup is retried as well
+    let trade = Trade {
+        side: Side::Sell,
+        wallet,
+        token,
+        market: Market::Unknown,
+        amount,
+        user,
+        tweet: String::new(),
+    };
+    execute_trade(trade, keypair, state).await
+}
+
+// Apply the dedup policy, reserving the buy if it is allowed
+fn reserve_buy(token: &str, user: &str, amount: f64, state: &State) -> Result<(), String> {
+    // Held until the buy settles, or for as long as its retries can possibly run
+    let hold = state
+        .config
+        .buy_config_for(user)
+        .retry
+        .max_duration(MAX_ATTEMPT_DURATION);
+    let journal = state.journal.lock().unwrap();
+    state.buy_guard.lock().unwrap().check(
+        &state.config.dedup,
+        &journal,
+        token,
+        user,
+        amount,
+        state.config.paper_trading,
+        hold,
+    )
+}
+
+fn check_cooldown(user: &str, state: &State) -> Result<(), String> {
+    let Some(cooldown_secs) = state.config.filter_for(user).cooldown_secs else {
+        return Ok(());
+    };
+    let journal = state.journal.lock().unwrap();
+    state.buy_guard.lock().unwrap().cooldown(
+        &journal,
+        user,
+        cooldown_secs,
+        state.config.paper_trading,
+    )
+}
+
+// Wait for a landed transaction and read the wallet's SOL and token deltas from its metadata
+async fn fetch_fill(
+    state: &State,
+    wallet: &str,
+    signature: &str,
+    mint: &str,
+) -> Result<(f64, f64), Box<dyn Error + Send + Sync>> {
+    let signature = Signature::from_str(signature)?;
+    let config = RpcTransactionConfig {
+        encoding: Some(UiTransactionEncoding::Json),
+        commitment: Some(CommitmentConfig::confirmed()),
+        max_supported_transaction_version: Some(0),
+    };
+
+    // The RPC may not serve a transaction right after it confirmed
+    let mut attempts = 1;
+    let mut delay = FILL_FIRST_DELAY;
+    let tx = loop {
+        match state
+            .rpc_client
+            .get_transaction_with_config(&signature, config)
+            .await
+        {
+            Ok(tx) => break tx,
+            Err(_) if attempts < FILL_ATTEMPTS => {
+                attempts += 1;
+                sleep(delay).await;
+                delay *= 2;
+            }
+            Err(e) => return Err(e.into()),
         }
+    };
+
+    let meta = tx
+        .transaction
+        .meta
+        .ok_or("Transaction has no status meta")?;
+    if let Some(err) = meta.err {
+        return Err(format!("Transaction failed: {err}").into());
     }
+
+    // The wallet pays the fees, so it is always the first account
+    let sol_delta = (meta.post_balances[0] as f64 - meta.pre_balances[0] as f64) / 1_000_000_000.0;
+
+    let owner = wallet.to_string();
+    let token_balance = |balances: OptionSerializer<Vec<UiTransactionTokenBalance>>| -> f64 {
+        Option::<Vec<UiTransactionTokenBalance>>::from(balances)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|b| {
+                b.mint == mint && Option::<String>::from(b.owner.clone()).as_ref() == Some(&owner)
+            })
+            .map(|b| {
+                b.ui_token_amount
+                    .ui_amount_string
+                    .parse::<f64>()
+                    .unwrap_or(0.0)
+            })
+            .sum()
+    };
+    let token_delta =
+        token_balance(meta.post_token_balances) - token_balance(meta.pre_token_balances);
+
+    Ok((sol_delta, token_delta))
 }
 
-pub async fn sell_token_task(
+// A trade sent on chain, followed from submission to its final stage
+struct Trade {
+    side: Side,
+    // Public key of the wallet trading
+    wallet: String,
     token: String,
+    market: Market,
+    // SOL spent by a buy, tokens sold by a sell
     amount: f64,
-    state: State,
-) -> Result<(), Box<dyn Error + Send + Sync>> {
-    let market_res = identify_markets(&token).await;
-
-    let sell_config = tmc_solana_proto::proto::SellConfig {
-        slippage: state.config.sell_config.slippage,
-        use_jito: state.config.sell_config.use_jito,
-        jito_tip: state.config.sell_config.jito_tip,
-        prio_fee: 0.0001,
-        sol_amount_left: 0.0,
-        sol_amount_right: 0.0,
-        ..Default::default()
+    user: String,
+    tweet: String,
+}
+
+impl Trade {
+    fn action(&self) -> String {
+        match self.side {
+            Side::Buy => format!("Buy {}", self.token),
+            Side::Sell => format!("Sell {}", self.token),
+        }
+    }
+}
+
+// Send a trade until it lands, retrying failed attempts as far as the retry policy allows
+async fn execute_trade(mut trade: Trade, keypair: Keypair, state: State) -> bool {
+    let (slippage, prio_fee, retry) = match trade.side {
+        Side::Buy => {
+            let config = state.config.buy_config_for(&trade.user);
+            (config.slippage, config.prio_fee, config.retry)
+        }
+        Side::Sell => {
+            let config = state.config.sell_config_for(&trade.user);
+            (config.slippage, config.prio_fee, config.retry)
+        }
     };
+    let action = trade.action();
+    let first = Instant::now();
+    let mut attempts = 0;
+    let mut slippage_bumps = 0;
+    // Buys come with the market their safety checks ran on, sells look it up
+    let mut identify = trade.side == Side::Sell;
+    let mut landed = false;
 
-    log::warn!(target:"app", "Selling {token}");
+    loop {
+        attempts += 1;
+        let attempt_slippage = retry.slippage(slippage, slippage_bumps);
+        // Every retry pays a higher fee to land sooner
+        let attempt_prio_fee = retry.prio_fee(prio_fee, attempts - 1);
+        if identify {
+            match market::identify(&state.rpc_client, &trade.token).await {
+                Ok(market) => {
+                    if market == Market::Unknown {
+                        log::warn!(target:"app", "No market found for {}, trading through Jupiter", trade.token);
+                    }
+                    trade.market = market;
+                    identify = false;
+                }
+                Err(e) => {
+                    report(
+                        &state,
+                        &trade,
+                        Level::Error,
+                        format!("{action}: unable to identify market: {e}"),
+                    );
+                }
+            }
+        }
+        let class = if identify {
+            FailureClass::Other
+        } else {
+            let started = Instant::now();
+            let sent = send_trade(
+                &trade,
+                keypair.insecure_clone(),
+                attempt_slippage,
+                attempt_prio_fee,
+                &state,
+            )
+            .await;
+            match follow_trade(&trade, sent, started, &state).await {
+                Ok(()) => {
+                    landed = true;
+                    break;
+                }
+                Err(class) => class,
+            }
+        };
 
-    match market_res {
-        Ok(market) => match market {
-            "PumpFun" => {
-                tokio::task::spawn(async move {
-                    let tx_id = state
+        match class {
+            // More attempts would fail the same way, or could land twice
+            FailureClass::InsufficientFunds | FailureClass::MaybeSent => {
+                report(
+                    &state,
+                    &trade,
+                    Level::Error,
+                    format!("{action}: {class}, not retrying"),
+                );
+                break;
+            }
+            _ if !retry.allows(attempts, first.elapsed()) => {
+                report(
+                    &state,
+                    &trade,
+                    Level::Error,
+                    format!("{action}: {class}, giving up after {attempts} attempts"),
+                );
+                break;
+            }
+            FailureClass::Slippage => slippage_bumps += 1,
+            // Route the next attempt through the engine of the token's current market
+            FailureClass::MarketChanged => identify = true,
+            // The engine builds every attempt on a fresh blockhash, so expired trades are
+            // simply sent again. The compute limit is the engine's, only the fee can change.
+            FailureClass::Expired | FailureClass::ComputeExhausted | FailureClass::Other => {}
+        }
+
+        report(
+            &state,
+            &trade,
+            Level::Warn,
+            format!(
+                "{action}: {class}, retrying (attempt {}, slippage {}, priority fee {})",
+                attempts + 1,
+                retry.slippage(slippage, slippage_bumps),
+                retry.prio_fee(prio_fee, attempts)
+            ),
+        );
+        sleep(RETRY_DELAY).await;
+    }
+    // A sell of the same mint must not release a buy still in flight
+    if trade.side == Side::Buy {
+        state.buy_guard.lock().unwrap().settle(&trade.token);
+    }
+    landed
+}
+
+// Build and send one attempt through the engine of the trade's market
+async fn send_trade(
+    trade: &Trade,
+    keypair: Keypair,
+    slippage: f64,
+    prio_fee: f64,
+    state: &State,
+) -> Result<String, Box<dyn Error + Send + Sync>> {
+    let mint = Pubkey::from_str(&trade.token)?;
+    let tx_id = match trade.side {
+        Side::Buy => {
+            let user_buy_config = state.config.buy_config_for(&trade.user);
+            let buy_config = tmc_solana_proto::proto::BuyConfig {
+                slippage,
+                use_jito: user_buy_config.use_jito,
+                jito_tip: user_buy_config.jito_tip,
+                autobuy: false,
+                prio_fee,
+                sol_amount_left: 0.0,
+                sol_amount_right: 0.0,
+                sol_amount_autobuy: 0.0,
+            };
+            match trade.market {
+                Market::PumpFun => {
+                    state
                         .pumpfun_engine
-                        .sell(
-                            state.wallet.insecure_clone(),
-                            Pubkey::from_str(&token).unwrap(),
-                            amount,
-                            state.config.sell_config.slippage,
-                            sell_config.clone(),
-                        )
-                        .await
-                        .unwrap();
-
-                    log::info!(target:"app", "Sold token! signature: {tx_id}");
-                });
+                        .buy(keypair, mint, trade.amount, slippage, buy_config)
+                        .await?
+                }
+                Market::PumpFunMigrated
+                | Market::RaydiumAmm
+                | Market::RaydiumClmm
+                | Market::Meteora
+                | Market::Orca
+                | Market::OtherDex
+                | Market::Unknown => {
+                    state
+                        .jupiter_engine
+                        .buy(keypair, mint, trade.amount, slippage, buy_config)
+                        .await?
+                }
             }
-            _ => {
-                tokio::task::spawn(async move {
-                    let tx_id = state
+        }
+        Side::Sell => {
+            let user_sell_config = state.config.sell_config_for(&trade.user);
+            let sell_config = tmc_solana_proto::proto::SellConfig {
+                slippage,
+                use_jito: user_sell_config.use_jito,
+                jito_tip: user_sell_config.jito_tip,
+                prio_fee,
+                sol_amount_left: 0.0,
+                sol_amount_right: 0.0,
+                ..Default::default()
+            };
+            match trade.market {
+                Market::PumpFun => {
+                    state
+                        .pumpfun_engine
+                        .sell(keypair, mint, trade.amount, slippage, sell_config)
+                        .await?
+                }
+                Market::PumpFunMigrated
+                | Market::RaydiumAmm
+                | Market::RaydiumClmm
+                | Market::Meteora
+                | Market::Orca
+                | Market::OtherDex
+                | Market::Unknown => {
+                    state
                         .jupiter_engine
-                        .sell(
-                            state.wallet.insecure_clone(),
-                            Pubkey::from_str(&token).unwrap(),
-                            amount,
-                            state.config.sell_config.slippage,
-                            sell_config.clone(),
-                        )
-                        .await
-                        .unwrap();
-
-                    log::info!(target:"app", "Sold token! signature: {tx_id}");
-                });
+                        .sell(keypair, mint, trade.amount, slippage, sell_config)
+                        .await?
+                }
             }
-        },
-        Err(_) => {
+        }
+    };
+    Ok(tx_id.to_string())
+}
+
+// Follow a sent trade through each stage, journaling the fill once it is confirmed.
+// Latency counts from the engine call, so building and sending are included.
+async fn follow_trade(
+    trade: &Trade,
+    sent: Result<String, Box<dyn Error + Send + Sync>>,
+    started: Instant,
+    state: &State,
+) -> Result<(), FailureClass> {
+    let action = trade.action();
+    let signature = match sent {
+        Ok(signature) => signature,
+        Err(e) => {
+            let message = e.to_string();
+            if !confirm::failed_before_broadcast(&message) {
+                report(
+                    state,
+                    trade,
+                    Level::Error,
+                    format!("{action} may have been sent: {message}"),
+                );
+                return Err(FailureClass::MaybeSent);
+            }
+            report(
+                state,
+                trade,
+                Level::Error,
+                format!("{action} not sent: {message}"),
+            );
+            return Err(FailureClass::of_message(&message));
+        }
+    };
+    tracing::info!("{action} sent: {signature}");
+
+    let mut tracker = match Tracker::new(&state.rpc_client, &signature, started).await {
+        Ok(tracker) => tracker,
+        Err(e) => {
+            // The trade may still land, so it is never sent again
+            report(
+                state,
+                trade,
+                Level::Error,
+                format!("{action}: unable to track {signature}: {e}"),
+            );
             return Ok(());
         }
+    };
+
+    let mut fill = None;
+    while let Some((stage, latency)) = tracker.next().await {
+        let latency = format!("{:.1}s", latency.as_secs_f64());
+        match &stage {
+            TxStage::Processed => {
+                report(
+                    state,
+                    trade,
+                    Level::Info,
+                    format!("{action}: processed after {latency}"),
+                );
+            }
+            TxStage::Confirmed | TxStage::Finalized => {
+                if fill.is_none() {
+                    fill = record_fill(trade, &signature, state).await;
+                }
+                let filled = match (fill, trade.side) {
+                    (Some((sol, tokens)), Side::Buy) => {
+                        format!(", got {tokens:.2} for {sol:.4} SOL")
+                    }
+                    (Some((sol, tokens)), Side::Sell) => {
+                        format!(", sold {tokens:.2} for {sol:.4} SOL")
+                    }
+                    (None, _) => ", fill unknown".into(),
+                };
+                report(
+                    state,
+                    trade,
+                    Level::Info,
+                    format!("{action}: {stage} after {latency}{filled}"),
+                );
+            }
+            TxStage::Failed(failure) => {
+                report(
+                    state,
+                    trade,
+                    Level::Error,
+                    format!("{action}: {stage} after {latency} ({signature})"),
+                );
+                return Err(failure.class);
+            }
+            TxStage::Expired => {
+                report(
+                    state,
+                    trade,
+                    Level::Error,
+                    format!("{action}: {stage} after {latency} ({signature})"),
+                );
+                return Err(FailureClass::Expired);
+            }
+        }
     }
     Ok(())
 }
 
-async fn expand_url(short_url: &str) -> Result<String, Box<dyn Error + Send + Sync>> {
-    let client = reqwest::Client::new();
-    let response = client.get(short_url).send().await?.error_for_status()?;
-    let final_url = response.url().clone();
-    Ok(final_url.to_string())
+// Show the progress of a trade in the log and in its user's row
+fn report(state: &State, trade: &Trade, level: Level, status: String) {
+    log::log!(target:"app", level, "{status}");
+    tracing::info!("{status}");
+    let _ = state.status_updates.send(StatusUpdate {
+        user: trade.user.clone(),
+        status,
+    });
+}
+
+// Record a landed trade in the journal, returning the SOL and token amounts filled
+async fn record_fill(trade: &Trade, signature: &str, state: &State) -> Option<(f64, f64)> {
+    let fill = match fetch_fill(state, &trade.wallet, signature, &trade.token).await {
+        Ok((sol_delta, token_delta)) => match trade.side {
+            Side::Buy => Ok((-sol_delta, token_delta)),
+            Side::Sell => Ok((sol_delta, -token_delta)),
+        },
+        Err(e) => Err(e),
+    };
+    // The trade landed, so it is journaled from a quote rather than left out of the positions
+    let (sol_amount, token_amount, estimated) = match fill {
+        Ok((sol_amount, token_amount)) => (sol_amount, token_amount, false),
+        Err(e) => {
+            log::warn!(target:"app", "Unable to read fill for {signature}, journaling a quote: {e}");
+            match quote_fill(trade, state).await {
+                Ok((sol_amount, token_amount)) => (sol_amount, token_amount, true),
+                Err(e) => {
+                    log::error!(target:"app", "Unable to quote fill for {signature}: {e}");
+                    return None;
+                }
+            }
+        }
+    };
+
+    state.journal.lock().unwrap().append(TradeRecord {
+        signature: signature.to_string(),
+        side: trade.side,
+        mint: trade.token.clone(),
+        market: trade.market,
+        sol_amount,
+        token_amount,
+        tweet: trade.tweet.clone(),
+        user: trade.user.clone(),
+        timestamp: chrono::Utc::now().timestamp(),
+        simulated: false,
+        estimated,
+        wallet: trade.wallet.clone(),
+    });
+    if trade.side == Side::Buy {
+        state.buy_guard.lock().unwrap().settle(&trade.token);
+    }
+    Some((sol_amount, token_amount))
+}
+
+// SOL and token amounts of a trade at the current quote
+async fn quote_fill(
+    trade: &Trade,
+    state: &State,
+) -> Result<(f64, f64), Box<dyn Error + Send + Sync>> {
+    let mint = Pubkey::from_str(&trade.token)?;
+    Ok(match trade.side {
+        Side::Buy => {
+            let tokens =
+                pricing::quote_buy(&state.rpc_client, trade.market, &mint, trade.amount).await?;
+            (trade.amount, tokens)
+        }
+        Side::Sell => {
+            let sol =
+                pricing::quote_sell(&state.rpc_client, trade.market, &mint, trade.amount).await?;
+            (sol, trade.amount)
+        }
+    })
+}
+
+// Simulate a buy at the quoted price and record it in the journal
+async fn paper_buy(
+    wallet: String,
+    token: String,
+    market: Market,
+    user: String,
+    tweet: String,
+    state: State,
+) {
+    let amount = state.config.buy_config_for(&user).amount;
+    let mint = match Pubkey::from_str(&token) {
+        Ok(mint) => mint,
+        Err(e) => {
+            log::error!(target:"app", "[SIM] Invalid mint {token}: {e}");
+            return;
+        }
+    };
+
+    match pricing::quote_buy(&state.rpc_client, market, &mint, amount).await {
+        Ok(tokens) => {
+            log::info!(target:"app", "[SIM] Bought {tokens:.2} {token} for {amount} SOL");
+            state.journal.lock().unwrap().append(TradeRecord {
+                signature: simulated_signature(),
+                side: Side::Buy,
+                mint: token.clone(),
+                market,
+                sol_amount: amount,
+                token_amount: tokens,
+                tweet,
+                user,
+                timestamp: chrono::Utc::now().timestamp(),
+                simulated: true,
+                estimated: false,
+                wallet,
+            });
+            state.buy_guard.lock().unwrap().settle(&token);
+        }
+        Err(e) => {
+            state.buy_guard.lock().unwrap().settle(&token);
+            log::error!(target:"app", "[SIM] Unable to quote buy for {token}: {e}");
+        }
+    }
+}
+
+// Simulate a sell at the quoted price and record it in the journal
+async fn paper_sell(wallet: String, token: String, amount: f64, state: State) -> bool {
+    let position = state
+        .journal
+        .lock()
+        .unwrap()
+        .wallet_position(&wallet, &token, true);
+    let Some(position) = position else {
+        log::error!(target:"app", "[SIM] No simulated position for {token}");
+        return false;
+    };
+    let mint = match Pubkey::from_str(&token) {
+        Ok(mint) => mint,
+        Err(e) => {
+            log::error!(target:"app", "[SIM] Invalid mint {token}: {e}");
+            return false;
+        }
+    };
+
+    log::warn!(target:"app", "[SIM] Selling {token}");
+
+    match pricing::quote_sell(&state.rpc_client, position.market, &mint, amount).await {
+        Ok(sol) => {
+            log::info!(target:"app", "[SIM] Sold {amount:.2} {token} for {sol:.5} SOL");
+            state.journal.lock().unwrap().append(TradeRecord {
+                signature: simulated_signature(),
+                side: Side::Sell,
+                mint: token,
+                market: position.market,
+                sol_amount: sol,
+                token_amount: amount,
+                tweet: String::new(),
+                user: position.user,
+                timestamp: chrono::Utc::now().timestamp(),
+                simulated: true,
+                estimated: false,
+                wallet,
+            });
+            true
+        }
+        Err(e) => {
+            log::error!(target:"app", "[SIM] Unable to quote sell for {token}: {e}");
+            false
+        }
+    }
+}
+
+fn simulated_signature() -> String {
+    format!(
+        "SIM-{}",
+        chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default()
+    )
+}
+
+// Labels callers put in front of the contract address
+const CA_LABELS: [&str; 4] = ["ca", "contract", "address", "mint"];
+
+struct Candidate {
+    address: Pubkey,
+    score: i32,
+}
+
+// Addresses that may be the mint a tweet is about, scored by how they were posted
+#[derive(Default)]
+struct Candidates(Vec<Candidate>);
+
+impl Candidates {
+    fn add(&mut self, address: Pubkey, score: i32) {
+        // SOL and stablecoins are what a token is quoted in, never the token itself
+        if links::QUOTE_MINTS.contains(&address.to_string().as_str()) {
+            return;
+        }
+        match self.0.iter_mut().find(|c| c.address == address) {
+            // Repeated mentions make a candidate more likely
+            Some(candidate) => candidate.score = candidate.score.max(score) + 1,
+            None => self.0.push(Candidate { address, score }),
+        }
+    }
+
+    // Addresses written in the tweet itself
+    fn add_from_tweet(&mut self, address_regex: &Regex, tweet: &str) {
+        for matched in address_regex.find_iter(tweet) {
+            // Only strings decoding to exactly 32 bytes are addresses
+            let Ok(address) = Pubkey::from_str(matched.as_str()) else {
+                continue;
+            };
+            let before = tweet[..matched.start()].to_lowercase();
+            let labelled = CA_LABELS.iter().any(|label| {
+                before
+                    .trim_end_matches(|c: char| c.is_whitespace() || c == ':' || c == '-')
+                    .ends_with(label)
+            });
+            let mut score = 2;
+            if labelled {
+                score += 2;
+            }
+            // Vanity suffix of pump.fun mints
+            if matched.as_str().ends_with("pump") {
+                score += 1;
+            }
+            self.add(address, score);
+        }
+    }
+
+    // The candidates `is_mint` accepts, highest score first
+    fn ranked(self, is_mint: impl Fn(&Pubkey) -> bool) -> Vec<String> {
+        let mut mints: Vec<Candidate> =
+            self.0.into_iter().filter(|c| is_mint(&c.address)).collect();
+        // Stable, so equal scores keep the order they were posted in
+        mints.sort_by_key(|c| std::cmp::Reverse(c.score));
+        mints.into_iter().map(|c| c.address.to_string()).collect()
+    }
+}
+
+// Whether an account is a mint of the Token or Token-2022 program
+fn is_mint_account(account: &Account, token_2022: &Pubkey) -> bool {
+    let len = account.data.len();
+    if account.owner == spl_token::id() {
+        len == spl_token::state::Mint::LEN
+    } else if account.owner == *token_2022 {
+        // Extended Token-2022 mints mark their account type after the padding
+        len == spl_token::state::Mint::LEN
+            || (len > TOKEN_2022_ACCOUNT_TYPE_OFFSET
+                && account.data[TOKEN_2022_ACCOUNT_TYPE_OFFSET] == TOKEN_2022_MINT_TYPE)
+    } else {
+        false
+    }
 }
 
-// Function to find a Solana token address in a tweet
-async fn find_solana_token_address(
+async fn find_solana_token_addresses(
+    state: &State,
     tweet: &str,
-) -> Result<Option<String>, Box<dyn Error + Send + Sync>> {
-    // Regular expression to match Solana token addresses
+    urls: &[String],
+) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+    // Regular expression to match base58 strings of a Solana address length
     let solana_address_regex = Regex::new(r"\b[A-HJ-NP-Za-km-z1-9]{32,44}\b")?;
-    // Regular expression to match shortened URLs
-    let tco_url_regex = Regex::new(r"https://t\.co/[A-Za-z0-9]+")?;
+    let rpc_client = &state.rpc_client;
 
-    // Check for any Solana token address directly in the tweet
-    if let Some(matched) = solana_address_regex.find(tweet) {
-        return Ok(Some(matched.as_str().to_string()));
-    }
+    let mut candidates = Candidates::default();
+    candidates.add_from_tweet(&solana_address_regex, tweet);
 
-    // Check for any shortened URLs and expand them
-    for short_url in tco_url_regex.find_iter(tweet) {
-        let expanded_url = expand_url(short_url.as_str()).await?;
-        if let Some(matched) = solana_address_regex.find(&expanded_url) {
-            return Ok(Some(matched.as_str().to_string()));
+    // Addresses in the links of the tweet, expanding shortened ones
+    for expanded_url in state.url_expander.expand_all(urls).await {
+        // Links to known sites say where the mint is, other links are scanned for addresses
+        if let Some(link) = links::parse(&expanded_url) {
+            match links::resolve(rpc_client, link.clone()).await {
+                Ok(Some(address)) => candidates.add(address, 1),
+                Ok(None) => {}
+                Err(e) => tracing::error!("Failed to resolve {link:?} from {expanded_url}: {e}"),
+            }
+            continue;
+        }
+        for matched in solana_address_regex.find_iter(&expanded_url) {
+            if let Ok(address) = Pubkey::from_str(matched.as_str()) {
+                candidates.add(address, 1);
+            }
         }
     }
 
-    Ok(None)
+    if candidates.0.is_empty() {
+        return Ok(vec![]);
+    }
+
+    // Keep the accounts that are mints
+    let addresses: Vec<Pubkey> = candidates.0.iter().map(|c| c.address).collect();
+    let accounts = rpc_client.get_multiple_accounts(&addresses).await?;
+    let token_2022 = Pubkey::from_str(TOKEN_2022_PROGRAM_ID)?;
+    let mints: Vec<Pubkey> = addresses
+        .into_iter()
+        .zip(accounts)
+        .filter(|(_, account)| {
+            account
+                .as_ref()
+                .is_some_and(|account| is_mint_account(account, &token_2022))
+        })
+        .map(|(address, _)| address)
+        .collect();
+    Ok(candidates.ranked(|address| mints.contains(address)))
 }
 
-pub async fn identify_markets(mint: &str) -> Result<&str, Box<dyn Error + Send + Sync>> {
-    let response = reqwest::Client::builder()
-        .build()?
-        .get(format!("https://frontend-api.pump.fun/coins/{}", mint))
-        .header("Accept", "application/json")
-        .send()
-        .await?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINT: &str = "7GCihgDB8fe6KNjn2MYtkzZcRjQy3t9GHdC8uHYmW2hr";
+    const OTHER_MINT: &str = "HeLp6NuQkmYB4pYWo2zYs22mESHXPQYzXbB8n4V98jwC";
+    const PUMP_MINT: &str = "9BB6NFEcjBCtnNLFko2FqVQBq8HHM13kCyYcdQbgpump";
+    const USDC: &str = "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v";
+
+    fn ranked(tweet: &str) -> Vec<String> {
+        let regex = Regex::new(r"\b[A-HJ-NP-Za-km-z1-9]{32,44}\b").unwrap();
+        let mut candidates = Candidates::default();
+        candidates.add_from_tweet(&regex, tweet);
+        candidates.ranked(|_| true)
+    }
+
+    #[test]
+    fn candidate_scoring() {
+        for (tweet, expected) in [
+            ("nothing here".to_string(), vec![]),
+            (MINT.to_string(), vec![MINT]),
+            // Posted order breaks ties
+            (format!("{MINT} {OTHER_MINT}"), vec![MINT, OTHER_MINT]),
+            // A label before the address outranks an unlabelled one
+            (format!("{MINT} CA: {OTHER_MINT}"), vec![OTHER_MINT, MINT]),
+            (
+                format!("{MINT} contract - {OTHER_MINT}"),
+                vec![OTHER_MINT, MINT],
+            ),
+            // So does the pump.fun vanity suffix
+            (format!("{MINT} {PUMP_MINT}"), vec![PUMP_MINT, MINT]),
+            // And a second mention
+            (
+                format!("{MINT} {OTHER_MINT} {OTHER_MINT}"),
+                vec![OTHER_MINT, MINT],
+            ),
+            // Base58 of the wrong length is not an address
+            (
+                "7GCihgDB8fe6KNjn2MYtkzZcRjQy3t9GHdC8uHYmW2".to_string(),
+                vec![],
+            ),
+        ] {
+            assert_eq!(ranked(&tweet), expected, "{tweet}");
+        }
+    }
+
+    #[test]
+    fn quote_mints_are_not_candidates() {
+        for quote in links::QUOTE_MINTS {
+            assert_eq!(ranked(&format!("CA: {quote} {MINT}")), [MINT]);
+        }
+        let mut candidates = Candidates::default();
+        candidates.add(Pubkey::from_str(USDC).unwrap(), 10);
+        assert!(candidates.0.is_empty());
+    }
+
+    #[test]
+    fn only_mints_are_ranked() {
+        let regex = Regex::new(r"\b[A-HJ-NP-Za-km-z1-9]{32,44}\b").unwrap();
+        let mut candidates = Candidates::default();
+        candidates.add_from_tweet(&regex, &format!("CA: {MINT} {OTHER_MINT}"));
+        let other = Pubkey::from_str(OTHER_MINT).unwrap();
+        assert_eq!(candidates.ranked(|address| *address == other), [OTHER_MINT]);
+    }
+
+    #[test]
+    fn mint_accounts() {
+        let token_2022 = Pubkey::from_str(TOKEN_2022_PROGRAM_ID).unwrap();
+        let account = |owner: Pubkey, data: Vec<u8>| Account {
+            lamports: 1,
+            data,
+            owner,
+            executable: false,
+            rent_epoch: 0,
+        };
+        let mut extended_mint = vec![0; TOKEN_2022_ACCOUNT_TYPE_OFFSET + 10];
+        extended_mint[TOKEN_2022_ACCOUNT_TYPE_OFFSET] = TOKEN_2022_MINT_TYPE;
+        let mut extended_account = extended_mint.clone();
+        extended_account[TOKEN_2022_ACCOUNT_TYPE_OFFSET] = 2;
+        let mint_len = spl_token::state::Mint::LEN;
+        let token_account_len = spl_token::state::Account::LEN;
 
-    if response.status() == reqwest::StatusCode::OK {
-        let data: Value = response.json().await?;
-        let is_raydium = !data["raydium_pool"].is_null();
-        if is_raydium {
-            return Ok("Raydium");
+        for (owner, data, expected) in [
+            (spl_token::id(), vec![0; mint_len], true),
+            (spl_token::id(), vec![0; token_account_len], false),
+            (token_2022, vec![0; mint_len], true),
+            (token_2022, extended_mint, true),
+            (token_2022, extended_account, false),
+            (token_2022, vec![0; token_account_len], false),
+            (Pubkey::new_unique(), vec![0; mint_len], false),
+        ] {
+            let len = data.len();
+            assert_eq!(
+                is_mint_account(&account(owner, data), &token_2022),
+                expected,
+                "{owner} {len}"
+            );
         }
-        return Ok("PumpFun");
     }
-    Ok("None")
 }
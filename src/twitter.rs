@@ -1,157 +1,330 @@
 use std::{
-    collections::HashMap,
-    error::Error,
-    str::FromStr,
-    sync::{mpsc::Sender, Arc},
+    collections::HashMap, error::Error, fs::File, io::BufReader, str::FromStr, sync::Arc,
     time::Duration,
 };
 
+use data_encoding::BASE32_NOPAD;
+use futures::future::BoxFuture;
+use hmac::{Hmac, Mac};
 use reqwest::{
     header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_TYPE, COOKIE, REFERER, USER_AGENT},
-    Client, Url,
+    Client, StatusCode, Url,
 };
 use reqwest_cookie_store::CookieStoreMutex;
 use serde_json::{json, Value};
-use tokio::time::{sleep, Instant};
+use sha1::Sha1;
+use tokio::{
+    sync::mpsc::UnboundedSender,
+    time::{sleep, Instant},
+};
 
-use crate::{tasks, ui::UserInfo, State};
+use crate::{
+    config::TwitterConfig,
+    graphql::{self, TimelineTweet},
+    keystore,
+    signals::{HealthReporter, Signal, SignalSource},
+};
 
-pub async fn monitor(
-    tx: Sender<Vec<UserInfo>>,
+// Watches the configured users through the home timeline of a followed-by account
+pub struct TwitterSource {
+    config: TwitterConfig,
     cookie_store: Arc<CookieStoreMutex>,
-    state: State,
-) -> Result<(), Box<dyn Error + Send + Sync>> {
-    let users = state.clone().config.users;
+    users: Vec<String>,
+}
+
+impl TwitterSource {
+    pub fn new(
+        config: TwitterConfig,
+        cookie_store: Arc<CookieStoreMutex>,
+        users: Vec<String>,
+    ) -> Self {
+        Self {
+            config,
+            cookie_store,
+            users,
+        }
+    }
+}
+
+impl SignalSource for TwitterSource {
+    fn name(&self) -> &'static str {
+        "twitter"
+    }
+
+    fn run(
+        &self,
+        signals: UnboundedSender<Signal>,
+        health: HealthReporter,
+    ) -> BoxFuture<'static, Result<(), Box<dyn Error + Send + Sync>>> {
+        Box::pin(monitor(
+            signals,
+            health,
+            self.config.clone(),
+            self.cookie_store.clone(),
+            self.users.clone(),
+        ))
+    }
+}
+
+// Consecutive failed timeline polls before the monitor gives up and gets restarted
+const MAX_POLL_FAILURES: u32 = 5;
+
+// Twitter rejected the session, a new login is needed
+#[derive(Debug)]
+struct AuthError(StatusCode);
+
+impl std::fmt::Display for AuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Twitter session rejected ({})", self.0)
+    }
+}
+
+impl Error for AuthError {}
 
+async fn monitor(
+    signals: UnboundedSender<Signal>,
+    health: HealthReporter,
+    config: TwitterConfig,
+    cookie_store: Arc<CookieStoreMutex>,
+    users: Vec<String>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
     let client = Client::builder()
         .cookie_store(true)
         .cookie_provider(cookie_store.clone())
         .build()?;
 
     let mut headers = get_headers();
+    let mut own_id = None;
+
+    if load_session(&cookie_store, &config.session_file) {
+        let mut session_headers = headers.clone();
+        match apply_session(&cookie_store, &mut session_headers) {
+            Ok(id) if verify_session(&client, &session_headers).await => {
+                log::info!(target:"app", "Restored Twitter session!");
+                headers = session_headers;
+                own_id = Some(id);
+            }
+            _ => {
+                log::warn!(target:"app", "Saved Twitter session is no longer valid");
+                cookie_store.lock().unwrap().clear();
+            }
+        }
+    }
 
-    tracing::info!("Starting log in");
-    log::warn!(target:"app", "Logging in Twitter!");
-    login(&client, &mut headers).await?;
-    log::info!(target:"app", "Logged in Twitter!");
-    tracing::info!("Logged in");
-
-    headers.insert(
-        "X-Twitter-Auth-Type",
-        HeaderValue::from_static("OAuth2Session"),
-    );
-    headers.insert("X-Twitter-Active-User", HeaderValue::from_static("yes"));
+    let own_id = match own_id {
+        Some(id) => id,
+        None => {
+            tracing::info!("Starting log in");
+            log::warn!(target:"app", "Logging in Twitter!");
+            login(&client, &mut headers, &config).await?;
+            log::info!(target:"app", "Logged in Twitter!");
+            tracing::info!("Logged in");
+
+            let id = apply_session(&cookie_store, &mut headers)?;
+            save_session(&cookie_store, &config.session_file);
+            id
+        }
+    };
 
-    let (cookie_header, csrf_token) = {
-        let cookie_jar = cookie_store.lock().unwrap();
-        let cookies = cookie_jar.get_request_values(&Url::from_str("https://twitter.com").unwrap());
-        let cookie_header: String = cookies
+    tracing::info!("Building Should follow");
+    let mut should_follow: Vec<String> = vec![];
+    let mut seen_tweets: Vec<String> = vec![];
+    let mut usernames: HashMap<String, String> = HashMap::new();
+    for u in users.iter() {
+        let id = get_user_id_by_screen_name(&client, &mut headers, u.to_string()).await?;
+        should_follow.push(id.clone());
+        usernames.insert(id, u.to_string());
+    }
+    tracing::info!("Building Should follow DONE");
+
+    // Only the difference is applied, so a restarted monitor does not churn its follows
+    if config.sync_follows {
+        let following = get_following(&client, &mut headers, own_id).await?;
+        let unfollow: Vec<String> = following
+            .iter()
+            .filter(|id| !should_follow.contains(id))
+            .cloned()
+            .collect();
+        let follow: Vec<String> = should_follow
             .into_iter()
-            .map(|c| format!("{}={}", c.0, c.1))
-            .collect::<Vec<String>>()
-            .join("; ");
-
-        let csrf_token = cookie_jar
-            .get("twitter.com", "/", "ct0")
-            .map(|cookie| cookie.value().to_string());
-
-        (cookie_header, csrf_token)
-    };
+            .filter(|id| !following.contains(id))
+            .collect();
 
-    if let Some(value) = csrf_token {
-        headers.insert("X-Csrf-Token", HeaderValue::from_str(&value).unwrap());
-        headers.insert(COOKIE, HeaderValue::from_str(&cookie_header)?);
-
-        tracing::info!("Building Should follow");
-        let mut should_follow: Vec<String> = vec![];
-        let mut seen_tweets: Vec<String> = vec![];
-        let mut user_info_map: HashMap<String, UserInfo> = HashMap::new();
-        for u in users {
-            let id = get_user_id_by_screen_name(&client, &mut headers, u.to_string()).await?;
-            should_follow.push(id.clone());
-            user_info_map.insert(
-                id,
-                UserInfo {
-                    username: u.to_string(),
-                    last_tweet: "".into(),
-                    status: "Waiting for Tweet".into(),
-                },
-            );
+        if !unfollow.is_empty() {
+            unfollow_users(&client, &mut headers, unfollow).await?;
+            sleep(Duration::from_secs(2)).await;
         }
-        tx.send(user_info_map.values().cloned().collect()).unwrap();
-        tracing::info!("Building Should follow DONE");
+        if !follow.is_empty() {
+            follow_users(&client, &mut headers, follow).await?;
+        }
+    }
 
-        let id = get_user_id_by_screen_name(&client, &mut headers, "lytraPoste52105".to_string())
-            .await?;
-        let following = get_following(&client, &mut headers, id).await?;
-        let _ = unfollow_users(&client, &mut headers, following).await?;
-
-        sleep(Duration::from_secs(2)).await;
-
-        let _ = follow_users(&client, &mut headers, should_follow).await?;
-
-        log::info!(target:"app", "Twitter monitor initialized and ready!");
-
-        let mut first = true;
-        loop {
-            let start = Instant::now();
-            match fetch_latest(&client, &mut headers, &mut seen_tweets).await {
-                Some(tweets) => {
-                    for msg in tweets.iter() {
-                        if !first {
-                            let status =
-                                tasks::start_user_tasks(msg.1.clone(), state.clone()).await?;
-                            if let Some(user) = user_info_map.get_mut(&msg.0) {
-                                user.last_tweet = msg.1.clone();
-                                user.status = status;
-                            }
-                            tx.send(user_info_map.values().cloned().collect()).unwrap();
-                        }
+    log::info!(target:"app", "Twitter monitor initialized and ready!");
+
+    let mut first = true;
+    let mut failures = 0;
+    loop {
+        let start = Instant::now();
+        match fetch_latest(&client, &mut headers, &mut seen_tweets).await {
+            Ok(tweets) => {
+                for tweet in tweets {
+                    if !first {
+                        let username = usernames
+                            .get(&tweet.user_id)
+                            .cloned()
+                            .unwrap_or_else(|| tweet.user_id.clone());
+                        signals.send(to_signal(username, tweet))?;
                     }
-                    first = false;
-                }
-                None => {
-                    eprintln!("Error fetching tweet");
                 }
+                first = false;
+                failures = 0;
+                health.polled();
             }
-            let elapsed = start.elapsed();
-            if elapsed < Duration::from_secs(2) {
-                sleep(Duration::from_secs(2) - elapsed).await;
+            Err(e) if e.is::<AuthError>() => {
+                // Forget the session so the restarted monitor logs in again
+                cookie_store.lock().unwrap().clear();
+                let _ = std::fs::remove_file(&config.session_file);
+                return Err(e);
             }
+            Err(e) => {
+                failures += 1;
+                tracing::error!("Error fetching tweets: {e}");
+                if failures >= MAX_POLL_FAILURES {
+                    return Err(format!("{failures} polls failed in a row, last error: {e}").into());
+                }
+            }
+        }
+        let elapsed = start.elapsed();
+        if elapsed < Duration::from_secs(2) {
+            sleep(Duration::from_secs(2) - elapsed).await;
         }
     }
+}
 
-    Ok(())
+fn to_signal(username: String, tweet: TimelineTweet) -> Signal {
+    let mut signal = Signal::new("twitter", tweet.id, username, tweet.text);
+    if let Some(created_at) = tweet.created_at {
+        signal.timestamp = created_at;
+    }
+    // Entities hold the expanded links, no need to follow t.co redirects
+    if !tweet.urls.is_empty() {
+        signal.urls = tweet.urls;
+    }
+    signal.kind = tweet.kind;
+    signal.quoted_text = tweet.quoted_text;
+    signal.cashtags = tweet.cashtags;
+    signal
+}
+
+// Load the cookies saved by a previous run, returns whether any were found
+fn load_session(cookie_store: &CookieStoreMutex, path: &str) -> bool {
+    let Ok(file) = File::open(path) else {
+        return false;
+    };
+    match cookie_store::serde::json::load(BufReader::new(file)) {
+        Ok(store) => {
+            let found = store.iter_unexpired().next().is_some();
+            *cookie_store.lock().unwrap() = store;
+            found
+        }
+        Err(e) => {
+            tracing::error!("Unable to load Twitter session: {e}");
+            false
+        }
+    }
+}
+
+fn save_session(cookie_store: &CookieStoreMutex, path: &str) {
+    let mut data = vec![];
+    // The cookies log the account in, so only the owner may read them
+    let result = cookie_store::serde::json::save_incl_expired_and_nonpersistent(
+        &cookie_store.lock().unwrap(),
+        &mut data,
+    )
+    .and_then(|()| keystore::write_private(path, data).map_err(|e| e.into()));
+    if let Err(e) = result {
+        tracing::error!("Unable to save Twitter session: {e}");
+    }
+}
+
+// Add the session cookies and csrf token to `headers`, returns the id of the logged in account
+fn apply_session(
+    cookie_store: &CookieStoreMutex,
+    headers: &mut HeaderMap,
+) -> Result<String, Box<dyn Error + Send + Sync>> {
+    let cookie_jar = cookie_store.lock().unwrap();
+    let cookies = cookie_jar.get_request_values(&Url::from_str("https://twitter.com")?);
+    let cookie_header: String = cookies
+        .into_iter()
+        .map(|c| format!("{}={}", c.0, c.1))
+        .collect::<Vec<String>>()
+        .join("; ");
+
+    let csrf_token = cookie_jar
+        .get("twitter.com", "/", "ct0")
+        .map(|cookie| cookie.value().to_string())
+        .ok_or("No csrf token in the Twitter session")?;
+
+    // `twid` holds the id of the logged in account as `"u=<id>"`, url encoded
+    let own_id = cookie_jar
+        .get("twitter.com", "/", "twid")
+        .or_else(|| cookie_jar.get("x.com", "/", "twid"))
+        .map(|cookie| {
+            cookie
+                .value()
+                .trim_matches('"')
+                .replace("%3D", "=")
+                .trim_start_matches("u=")
+                .to_string()
+        })
+        .ok_or("Unable to read the logged in account id")?;
+
+    headers.insert(
+        "X-Twitter-Auth-Type",
+        HeaderValue::from_static("OAuth2Session"),
+    );
+    headers.insert("X-Twitter-Active-User", HeaderValue::from_static("yes"));
+    headers.insert("X-Csrf-Token", HeaderValue::from_str(&csrf_token)?);
+    headers.insert(COOKIE, HeaderValue::from_str(&cookie_header)?);
+
+    Ok(own_id)
+}
+
+// Cheap authenticated call to check a restored session
+async fn verify_session(client: &Client, headers: &HeaderMap) -> bool {
+    match client
+        .get("https://api.twitter.com/1.1/account/settings.json")
+        .headers(headers.clone())
+        .send()
+        .await
+    {
+        Ok(res) => res.status().is_success(),
+        Err(e) => {
+            tracing::error!("Unable to verify Twitter session: {e}");
+            false
+        }
+    }
 }
 
 pub async fn fetch_latest(
     client: &Client,
     headers: &mut HeaderMap,
     seen_tweets: &mut Vec<String>,
-) -> Option<Vec<(String, String)>> {
-    match get_latest_timeline(&client, headers, seen_tweets).await {
-        Ok(tweets) => {
-            let new = check_if_new_tweet(tweets, seen_tweets);
-            return Some(new);
-        }
-        Err(_) => {
-            return None;
-        }
-    }
+) -> Result<Vec<TimelineTweet>, Box<dyn Error + Send + Sync>> {
+    let tweets = get_latest_timeline(client, headers, seen_tweets).await?;
+    Ok(check_if_new_tweet(tweets, seen_tweets))
 }
 
 pub fn check_if_new_tweet(
-    tweets: HashMap<String, Vec<(String, String)>>,
+    tweets: Vec<TimelineTweet>,
     seen_tweets: &mut Vec<String>,
-) -> Vec<(String, String)> {
+) -> Vec<TimelineTweet> {
     let mut res = vec![];
-    for (user, tweets_map) in &tweets {
-        for tweet in tweets_map.iter() {
-            if !seen_tweets.contains(&tweet.1) {
-                tracing::info!("New Tweet: {}", tweet.0);
-                res.push((user.to_string(), tweet.0.to_string()));
-                seen_tweets.push(tweet.1.clone());
-            }
+    for tweet in tweets {
+        if !seen_tweets.contains(&tweet.id) {
+            tracing::info!("New Tweet: {}", tweet.text);
+            seen_tweets.push(tweet.id.clone());
+            res.push(tweet);
         }
     }
 
@@ -161,9 +334,8 @@ pub fn check_if_new_tweet(
 pub async fn get_latest_timeline(
     _client: &Client,
     headers: &mut HeaderMap,
-    seen_tweets: &mut Vec<String>,
-) -> Result<HashMap<String, Vec<(String, String)>>, Box<dyn Error>> {
-    let mut tweets: HashMap<String, Vec<(String, String)>> = HashMap::new();
+    seen_tweets: &[String],
+) -> Result<Vec<TimelineTweet>, Box<dyn Error + Send + Sync>> {
     let params = json!({
         "variables": {
             "count": 20,
@@ -171,7 +343,7 @@ pub async fn get_latest_timeline(
             "latestControlAvailable": true,
             "requestContext": "launch",
             "withCommunity": true,
-            "seenTweetIds": seen_tweets.clone(),
+            "seenTweetIds": seen_tweets,
         },
         "queryId": "U0cdisy7QFIoTfu3-Okw0A",
         "features": {
@@ -207,47 +379,19 @@ pub async fn get_latest_timeline(
         .headers(headers.clone())
         .json(&params);
     let res = req.send().await?;
-
-    let text: Value = res.json().await?;
-    let instructions = text["data"]["home"]["home_timeline_urt"]["instructions"]
-        .as_array()
-        .unwrap();
-    for instruction in instructions.iter() {
-        if instruction["entries"].is_array() {
-            for entry in instruction["entries"].as_array().unwrap().iter() {
-                if entry["entryId"].as_str().unwrap().starts_with("tweet") {
-                    let id = entry["content"]["itemContent"]["tweet_results"]["result"]["legacy"]
-                        ["user_id_str"]
-                        .as_str()
-                        .unwrap()
-                        .to_string();
-                    let tweet_id = entry["content"]["itemContent"]["tweet_results"]["result"]
-                        ["legacy"]["id_str"]
-                        .as_str()
-                        .unwrap()
-                        .to_string();
-                    let tweet = entry["content"]["itemContent"]["tweet_results"]["result"]
-                        ["legacy"]["full_text"]
-                        .as_str()
-                        .unwrap()
-                        .to_string();
-                    tweets
-                        .entry(id)
-                        .or_insert_with(Vec::new)
-                        .push((tweet, tweet_id));
-                }
-            }
-        }
+    if res.status() == StatusCode::UNAUTHORIZED || res.status() == StatusCode::FORBIDDEN {
+        return Err(AuthError(res.status()).into());
     }
 
-    Ok(tweets.clone())
+    let text = res.text().await?;
+    graphql::parse_home_timeline(&text)
 }
 
 #[allow(dead_code)]
 pub async fn get_latest_tweet(
     _client: &Client,
     headers: &mut HeaderMap,
-) -> Result<String, Box<dyn Error>> {
+) -> Result<String, Box<dyn Error + Send + Sync>> {
     let url = "https://twitter.com/i/api/graphql/vMkJyzx1wdmvOeeNG0n6Wg/UserTweetsAndReplies?variables=%7B%22userId%22%3A%20%221790897799313154048%22%2C%20%22count%22%3A%201%2C%20%22includePromotedContent%22%3A%20true%2C%20%22withQuickPromoteEligibilityTweetFields%22%3A%20true%2C%20%22withVoice%22%3A%20true%2C%20%22withV2Timeline%22%3A%20true%7D&features=%7B%22creator_subscriptions_tweet_preview_api_enabled%22%3A%20true%2C%20%22c9s_tweet_anatomy_moderator_badge_enabled%22%3A%20true%2C%20%22tweetypie_unmention_optimization_enabled%22%3A%20true%2C%20%22responsive_web_edit_tweet_api_enabled%22%3A%20true%2C%20%22graphql_is_translatable_rweb_tweet_is_translatable_enabled%22%3A%20true%2C%20%22view_counts_everywhere_api_enabled%22%3A%20true%2C%20%22longform_notetweets_consumption_enabled%22%3A%20true%2C%20%22responsive_web_twitter_article_tweet_consumption_enabled%22%3A%20true%2C%20%22tweet_awards_web_tipping_enabled%22%3A%20false%2C%20%22longform_notetweets_rich_text_read_enabled%22%3A%20true%2C%20%22longform_notetweets_inline_media_enabled%22%3A%20true%2C%20%22rweb_video_timestamps_enabled%22%3A%20true%2C%20%22responsive_web_graphql_exclude_directive_enabled%22%3A%20true%2C%20%22verified_phone_label_enabled%22%3A%20false%2C%20%22freedom_of_speech_not_reach_fetch_enabled%22%3A%20true%2C%20%22standardized_nudges_misinfo%22%3A%20true%2C%20%22tweet_with_visibility_results_prefer_gql_limited_actions_policy_enabled%22%3A%20true%2C%20%22responsive_web_media_download_video_enabled%22%3A%20false%2C%20%22responsive_web_graphql_skip_user_profile_image_extensions_enabled%22%3A%20false%2C%20%22responsive_web_graphql_timeline_navigation_enabled%22%3A%20true%2C%20%22responsive_web_enhance_cards_enabled%22%3A%20false%7D";
 
     // let res = client.get(url).headers(headers.clone()).send().await?;
@@ -257,14 +401,13 @@ pub async fn get_latest_tweet(
         .headers(headers.clone())
         .send()
         .await?;
-    let text: Value = res.json().await?;
-    let tweet = text["data"]["user"]["result"]["timeline_v2"]["timeline"]["instructions"][1]
-        ["entries"][0]["content"]["itemContent"]["tweet_results"]["result"]["legacy"]["full_text"]
-        .as_str()
-        .unwrap()
-        .to_string();
+    let text = res.text().await?;
+    let tweet = graphql::parse_user_timeline(&text)?
+        .into_iter()
+        .next()
+        .ok_or("No tweet in the user timeline")?;
 
-    Ok(tweet)
+    Ok(tweet.text)
 }
 
 pub async fn follow_users(
@@ -318,32 +461,11 @@ pub async fn get_following(
 ) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
     let url = format!("https://twitter.com/i/api/graphql/2vUj-_Ek-UmBVDNtd8OnQA/Following?variables=%7B%22userId%22%3A%20%22{id}%22%2C%20%22count%22%3A%2020%2C%20%22includePromotedContent%22%3A%20false%7D&features=%7B%22creator_subscriptions_tweet_preview_api_enabled%22%3A%20true%2C%20%22c9s_tweet_anatomy_moderator_badge_enabled%22%3A%20true%2C%20%22tweetypie_unmention_optimization_enabled%22%3A%20true%2C%20%22responsive_web_edit_tweet_api_enabled%22%3A%20true%2C%20%22graphql_is_translatable_rweb_tweet_is_translatable_enabled%22%3A%20true%2C%20%22view_counts_everywhere_api_enabled%22%3A%20true%2C%20%22longform_notetweets_consumption_enabled%22%3A%20true%2C%20%22responsive_web_twitter_article_tweet_consumption_enabled%22%3A%20true%2C%20%22tweet_awards_web_tipping_enabled%22%3A%20false%2C%20%22longform_notetweets_rich_text_read_enabled%22%3A%20true%2C%20%22longform_notetweets_inline_media_enabled%22%3A%20true%2C%20%22rweb_video_timestamps_enabled%22%3A%20true%2C%20%22responsive_web_graphql_exclude_directive_enabled%22%3A%20true%2C%20%22verified_phone_label_enabled%22%3A%20false%2C%20%22freedom_of_speech_not_reach_fetch_enabled%22%3A%20true%2C%20%22standardized_nudges_misinfo%22%3A%20true%2C%20%22tweet_with_visibility_results_prefer_gql_limited_actions_policy_enabled%22%3A%20true%2C%20%22responsive_web_media_download_video_enabled%22%3A%20false%2C%20%22responsive_web_graphql_skip_user_profile_image_extensions_enabled%22%3A%20false%2C%20%22responsive_web_graphql_timeline_navigation_enabled%22%3A%20true%2C%20%22responsive_web_enhance_cards_enabled%22%3A%20false%7D");
 
-    let mut users: Vec<String> = vec![];
-
     let req = Client::builder().build()?.get(url).headers(headers.clone());
     let res = req.send().await?;
-    let text: Value = res.json().await?;
-    let instructions = text["data"]["user"]["result"]["timeline"]["timeline"]["instructions"]
-        .as_array()
-        .unwrap();
-    for instruction in instructions.iter() {
-        if instruction["entries"].is_array() {
-            if instruction["entries"][0]["entryId"]
-                .as_str()
-                .unwrap()
-                .starts_with("user")
-            {
-                let id = instruction["entries"][0]["content"]["itemContent"]["user_results"]
-                    ["result"]["rest_id"]
-                    .as_str()
-                    .unwrap()
-                    .to_string();
-                users.push(id);
-            }
-        }
-    }
+    let text = res.text().await?;
 
-    Ok(users)
+    graphql::parse_following(&text)
 }
 
 pub async fn unfollow_users(
@@ -399,41 +521,137 @@ pub async fn get_user_id_by_screen_name(
 
     let req = Client::builder().build()?.get(url).headers(headers.clone());
     let res = req.send().await?;
-    let text: Value = res.json().await?;
-    let id = text["data"]["user"]["result"]["rest_id"]
-        .as_str()
-        .unwrap()
-        .to_string();
+    let text = res.text().await?;
+    graphql::parse_user_id(&text).map_err(|e| format!("Unable to look up {name}: {e}").into())
+}
+
+// Onboarding subtasks the login flow knows how to answer
+const SUBTASK_JS_INSTRUMENTATION: &str = "LoginJsInstrumentationSubtask";
+const SUBTASK_USER_IDENTIFIER: &str = "LoginEnterUserIdentifierSSO";
+const SUBTASK_ALTERNATE_IDENTIFIER: &str = "LoginEnterAlternateIdentifierSubtask";
+const SUBTASK_PASSWORD: &str = "LoginEnterPassword";
+const SUBTASK_TWO_FACTOR: &str = "LoginTwoFactorAuthChallenge";
+const SUBTASK_ACID: &str = "LoginAcid";
+const SUBTASK_DUPLICATION_CHECK: &str = "AccountDuplicationCheck";
+const SUBTASK_DENY_LOGIN: &str = "DenyLoginSubtask";
+const SUBTASK_SUCCESS: &str = "LoginSuccessSubtask";
+
+// Upper bound on answered subtasks, Twitter should never need this many
+const MAX_LOGIN_STEPS: usize = 16;
+
+// The state of the onboarding flow after a request
+struct OnboardingStep {
+    flow_token: String,
+    subtask: Option<Value>,
+}
 
-    Ok(id)
+impl OnboardingStep {
+    fn subtask_id(&self) -> Option<&str> {
+        self.subtask.as_ref()?["subtask_id"].as_str()
+    }
 }
 
 pub async fn login(
     client: &Client,
     headers: &mut HeaderMap,
+    config: &TwitterConfig,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
     // First request to get guest token
-    let guest_token = get_guest_token(&client, &headers).await?;
+    let guest_token = get_guest_token(client, headers).await?;
 
     // Update headers with guest token
     headers.insert("x-guest-token", HeaderValue::from_str(&guest_token)?);
 
-    // Login flow steps
-    let flow_token = initiate_login_flow(&client, &headers).await?;
-    let username = "ztnaohzd";
-    let _email = "ztnaohzd@sharklasers.com";
-    let password = "dL2FhRW8u9Mx"; // Replace with actual password
-
-    let (flow_token, task_id) = submit_username(&client, &headers, &flow_token, username).await?;
+    let password = config.password()?;
+    let mut step = initiate_login_flow(client, headers).await?;
+
+    // Answer whatever Twitter asks for until the flow completes
+    for _ in 0..MAX_LOGIN_STEPS {
+        let Some(subtask_id) = step.subtask_id().map(|id| id.to_string()) else {
+            return Ok(());
+        };
+        tracing::info!("Twitter login step: {subtask_id}");
+
+        let input = match subtask_id.as_str() {
+            SUBTASK_SUCCESS => return Ok(()),
+            SUBTASK_JS_INSTRUMENTATION => json!({
+                "subtask_id": subtask_id,
+                "js_instrumentation": {
+                    "response": "{}",
+                    "link": "next_link"
+                }
+            }),
+            SUBTASK_USER_IDENTIFIER => json!({
+                "subtask_id": subtask_id,
+                "settings_list": {
+                    "setting_responses": [
+                        {
+                            "key": "user_identifier",
+                            "response_data": {
+                                "text_data": {
+                                    "result": config.identifier
+                                }
+                            }
+                        }
+                    ],
+                    "link": "next_link"
+                }
+            }),
+            SUBTASK_ALTERNATE_IDENTIFIER | SUBTASK_ACID => {
+                let email = config.email.as_ref().ok_or(format!(
+                    "Twitter login asked for the account email or phone ({subtask_id}), set twitter.email"
+                ))?;
+                json!({
+                    "subtask_id": subtask_id,
+                    "enter_text": {
+                        "text": email,
+                        "link": "next_link"
+                    }
+                })
+            }
+            SUBTASK_PASSWORD => json!({
+                "subtask_id": subtask_id,
+                "enter_password": {
+                    "password": password,
+                    "link": "next_link"
+                }
+            }),
+            SUBTASK_TWO_FACTOR => {
+                let secret = config
+                    .totp_secret
+                    .as_ref()
+                    .ok_or("Twitter login asked for a two-factor code, set twitter.totp_secret")?;
+                json!({
+                    "subtask_id": subtask_id,
+                    "enter_text": {
+                        "text": totp_code(secret, chrono::Utc::now().timestamp() as u64)?,
+                        "link": "next_link"
+                    }
+                })
+            }
+            SUBTASK_DUPLICATION_CHECK => json!({
+                "subtask_id": subtask_id,
+                "check_logged_in_account": {
+                    "link": "AccountDuplicationCheck_false"
+                }
+            }),
+            SUBTASK_DENY_LOGIN => {
+                let subtask = step.subtask.as_ref().unwrap();
+                let reason = subtask["cta"]["secondary_text"]["text"]
+                    .as_str()
+                    .or(subtask["cta"]["primary_text"]["text"].as_str())
+                    .unwrap_or("no reason given");
+                return Err(format!("Twitter denied the login: {reason}").into());
+            }
+            other => {
+                return Err(format!("Unsupported Twitter login step: {other}").into());
+            }
+        };
 
-    if task_id == "LoginEnterAlternateIdentifierSubtask" {
-        return Err("Not implemented yet".into());
+        step = submit_subtask(client, headers, &step.flow_token, input).await?;
     }
 
-    let flow_token = submit_password(&client, &headers, &flow_token, password).await?;
-    complete_login(&client, &headers, &flow_token).await?;
-
-    Ok(())
+    Err(format!("Twitter login did not finish after {MAX_LOGIN_STEPS} steps").into())
 }
 
 // Helper function to get guest token
@@ -447,59 +665,35 @@ async fn get_guest_token(
         .send()
         .await?;
     let res: Value = res.json().await?;
-    Ok(res["guest_token"].as_str().unwrap().to_string())
+    Ok(res["guest_token"]
+        .as_str()
+        .ok_or("No guest token in the Twitter response")?
+        .to_string())
 }
 
 // Helper function to initiate login flow
 async fn initiate_login_flow(
     client: &Client,
     headers: &HeaderMap,
-) -> Result<String, Box<dyn Error + Send + Sync>> {
+) -> Result<OnboardingStep, Box<dyn Error + Send + Sync>> {
     let res = client
         .post("https://api.twitter.com/1.1/onboarding/task.json?flow_name=login")
         .headers(headers.clone())
         .send()
         .await?;
-    let res: Value = res.json().await?;
-    let flow_token: String = res["flow_token"].as_str().unwrap().to_string();
-
-    let res = client
-        .post("https://api.twitter.com/1.1/onboarding/task.json")
-        .headers(headers.clone())
-        .json(&json!({ "flow_token": flow_token }))
-        .send()
-        .await?;
-    let res: Value = res.json().await?;
-    Ok(res["flow_token"].as_str().unwrap().to_string())
+    parse_onboarding_step(res.json().await?)
 }
 
-// Helper function to submit username
-async fn submit_username(
+// Helper function to answer one subtask of the login flow
+async fn submit_subtask(
     client: &Client,
     headers: &HeaderMap,
     flow_token: &str,
-    username: &str,
-) -> Result<(String, String), Box<dyn Error + Send + Sync>> {
+    input: Value,
+) -> Result<OnboardingStep, Box<dyn Error + Send + Sync>> {
     let data = json!({
         "flow_token": flow_token,
-        "subtask_inputs": [
-             {
-                "subtask_id": "LoginEnterUserIdentifierSSO",
-                "settings_list": {
-                    "setting_responses": [
-                        {
-                            "key": "user_identifier",
-                            "response_data": {
-                                "text_data": {
-                                    "result": username
-                                }
-                            }
-                        }
-                    ],
-                    "link": "next_link"
-                }
-            }
-        ]
+        "subtask_inputs": [input]
     });
 
     let res = client
@@ -508,72 +702,40 @@ async fn submit_username(
         .json(&data)
         .send()
         .await?;
-    let res: Value = res.json().await?;
-    Ok((
-        res["flow_token"].as_str().unwrap().to_string(),
-        res["subtasks"][0]["subtask_id"]
-            .as_str()
-            .unwrap()
-            .to_string(),
-    ))
+    parse_onboarding_step(res.json().await?)
 }
 
-// Helper function to submit password
-async fn submit_password(
-    client: &Client,
-    headers: &HeaderMap,
-    flow_token: &str,
-    password: &str,
-) -> Result<String, Box<dyn Error + Send + Sync>> {
-    let data = json!({
-        "flow_token": flow_token,
-        "subtask_inputs": [
-             {
-                "subtask_id": "LoginEnterPassword",
-                "enter_password": {
-                    "password": password,
-                    "link": "next_link"
-                }
-            }
-        ]
-    });
+fn parse_onboarding_step(res: Value) -> Result<OnboardingStep, Box<dyn Error + Send + Sync>> {
+    if let Some(message) = res["errors"][0]["message"].as_str() {
+        return Err(format!("Twitter login failed: {message}").into());
+    }
+    let flow_token = res["flow_token"]
+        .as_str()
+        .ok_or("No flow token in the Twitter login response")?
+        .to_string();
+    let subtask = res["subtasks"]
+        .as_array()
+        .and_then(|subtasks| subtasks.first())
+        .cloned();
 
-    let res = client
-        .post("https://api.twitter.com/1.1/onboarding/task.json")
-        .headers(headers.clone())
-        .json(&data)
-        .send()
-        .await?;
-    let res: Value = res.json().await?;
-    Ok(res["flow_token"].as_str().unwrap().to_string())
+    Ok(OnboardingStep {
+        flow_token,
+        subtask,
+    })
 }
 
-// Helper function to complete login
-async fn complete_login(
-    client: &Client,
-    headers: &HeaderMap,
-    flow_token: &str,
-) -> Result<(), Box<dyn Error + Send + Sync>> {
-    let data = json!({
-        "flow_token": flow_token,
-        "subtask_inputs": [
-             {
-                "subtask_id": "AccountDuplicationCheck",
-                "check_logged_in_account": {
-                    "link": "AccountDuplicationCheck_false"
-                }
-            }
-        ]
-    });
+// RFC 6238 code (SHA-1, 30 second step, 6 digits) for a base32 secret
+fn totp_code(secret: &str, unix_time: u64) -> Result<String, Box<dyn Error + Send + Sync>> {
+    let secret = secret.replace(' ', "").to_uppercase();
+    let key = BASE32_NOPAD.decode(secret.trim_end_matches('=').as_bytes())?;
 
-    let _res = client
-        .post("https://api.twitter.com/1.1/onboarding/task.json")
-        .headers(headers.clone())
-        .json(&data)
-        .send()
-        .await?;
+    let mut mac = Hmac::<Sha1>::new_from_slice(&key)?;
+    mac.update(&(unix_time / 30).to_be_bytes());
+    let hash = mac.finalize().into_bytes();
 
-    Ok(())
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let code = u32::from_be_bytes(hash[offset..offset + 4].try_into()?) & 0x7fff_ffff;
+    Ok(format!("{:06}", code % 1_000_000))
 }
 
 pub fn get_headers() -> HeaderMap {
@@ -595,3 +757,41 @@ pub fn get_headers() -> HeaderMap {
 
     headers
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // ASCII "12345678901234567890", the SHA-1 secret of RFC 6238 appendix B
+    const RFC_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+    #[test]
+    fn rfc_6238_vectors() {
+        // The RFC lists 8 digit codes, these are their last 6
+        for (time, code) in [
+            (59, "287082"),
+            (1111111109, "081804"),
+            (1111111111, "050471"),
+            (1234567890, "005924"),
+            (2000000000, "279037"),
+            (20000000000, "353130"),
+        ] {
+            assert_eq!(totp_code(RFC_SECRET, time).unwrap(), code, "T={time}");
+        }
+    }
+
+    #[test]
+    fn secret_formatting() {
+        // Secrets are often shown lowercase, grouped or padded
+        let code = totp_code(RFC_SECRET, 59).unwrap();
+        assert_eq!(
+            totp_code("gezd gnbv gy3t qojq gezd gnbv gy3t qojq", 59).unwrap(),
+            code
+        );
+        assert_eq!(
+            totp_code("GEZDGNBVGY3TQOJQ====", 59).unwrap(),
+            totp_code("GEZDGNBVGY3TQOJQ", 59).unwrap()
+        );
+        assert!(totp_code("not base32!", 59).is_err());
+    }
+}
@@ -8,19 +8,22 @@ use ratatui::backend::CrosstermBackend;
 use ratatui::layout::{Alignment, Constraint, Direction, Layout, Rect};
 use ratatui::style::{Color, Modifier, Style};
 use ratatui::text::{Line, Span, Text};
+use ratatui::widgets::block::Title;
 use ratatui::widgets::{Block, Borders, Cell, Paragraph, Row, Table, TableState};
 use ratatui::Terminal;
-use serde_json::Value;
 use solana_client::nonblocking::rpc_client::RpcClient;
-use solana_sdk::signature::Keypair;
 use solana_sdk::signer::Signer;
-use std::error::Error;
+use std::collections::HashMap;
 use std::io;
 use std::sync::mpsc::{self, Receiver, Sender};
 use std::sync::Arc;
 use tokio::time::{self, sleep, Duration};
 use tui_logger::{TuiLoggerWidget, TuiWidgetState};
 
+use crate::journal::PositionBook;
+use crate::positions::{Position, PositionTracker};
+use crate::signals::SourceStatus;
+use crate::strategy::position_key;
 use crate::{tasks, State};
 
 #[derive(Clone, Debug)]
@@ -31,8 +34,11 @@ pub struct UserInfo {
 }
 
 pub struct WalletInfo {
+    pub name: String,
+    pub pubkey: String,
     pub balance: u64,
-    pub owned_tokens: Vec<(String, String, String, String, String, f64)>,
+    pub owned_tokens: Vec<Position>,
+    pub realized_pnl: f64,
 }
 
 pub struct StatefulTable<T> {
@@ -51,7 +57,7 @@ impl<T> StatefulTable<T> {
     fn next(&mut self) {
         let i = match self.state.selected() {
             Some(i) => {
-                if self.items.len() == 0 || i >= self.items.len() - 1 {
+                if self.items.is_empty() || i >= self.items.len() - 1 {
                     0
                 } else {
                     i + 1
@@ -66,7 +72,7 @@ impl<T> StatefulTable<T> {
         let i = match self.state.selected() {
             Some(i) => {
                 if i == 0 {
-                    if self.items.len() == 0 {
+                    if self.items.is_empty() {
                         0
                     } else {
                         self.items.len() - 1
@@ -82,7 +88,6 @@ impl<T> StatefulTable<T> {
 }
 
 pub async fn run_ui(
-    keypair: Keypair,
     state: State,
     receiver: Receiver<Vec<UserInfo>>,
     rpc_client: Arc<RpcClient>,
@@ -96,29 +101,74 @@ pub async fn run_ui(
     let mut interval = time::interval(Duration::from_millis(100));
     let mut user_data: Vec<UserInfo> = vec![];
 
-    let (tx, rx): (Sender<WalletInfo>, Receiver<WalletInfo>) = mpsc::channel();
-    let pubkey = keypair.pubkey();
-    let mut wallet_info_state = WalletInfo {
-        balance: 0,
-        owned_tokens: vec![],
-    };
-    let mut stateful_wallet_table =
-        StatefulTable::with_items(wallet_info_state.owned_tokens.clone());
+    let (tx, rx): (Sender<Vec<WalletInfo>>, Receiver<Vec<WalletInfo>>) = mpsc::channel();
+    let mut wallet_infos: Vec<WalletInfo> = state
+        .wallets
+        .all()
+        .iter()
+        .map(|wallet| WalletInfo {
+            name: wallet.name.clone(),
+            pubkey: wallet.pubkey(),
+            balance: 0,
+            owned_tokens: vec![],
+            realized_pnl: 0.0,
+        })
+        .collect();
+    // Index of the wallet shown in the wallet panel
+    let mut selected_wallet = 0;
+    let mut stateful_wallet_table: StatefulTable<Position> = StatefulTable::with_items(vec![]);
 
     let mut show_confirmation = false;
     let confirmation_message = "Are you sure you want to sell? (y/n)";
 
     let cloned_state = state.clone();
     tokio::task::spawn(async move {
+        let mut tracker = PositionTracker::default();
         loop {
-            let b = rpc_client.get_balance(&pubkey).await.unwrap();
-            let owned = get_owned_tokens(pubkey.to_string()).await.unwrap();
-            check_auto_sell(owned.clone(), cloned_state.clone());
-            tx.send(WalletInfo {
-                balance: b,
-                owned_tokens: owned,
-            })
-            .unwrap();
+            let mut infos = vec![];
+            for wallet in cloned_state.wallets.all() {
+                let pubkey = wallet.keypair.pubkey();
+                let b = match rpc_client.get_balance(&pubkey).await {
+                    Ok(b) => b,
+                    Err(e) => {
+                        tracing::error!("Unable to load balance of {}: {e}", wallet.name);
+                        break;
+                    }
+                };
+                let owned = if cloned_state.config.paper_trading {
+                    tracker.get_paper_positions(&cloned_state, &pubkey).await
+                } else {
+                    tracker.get_positions(&cloned_state, &pubkey).await
+                };
+                let owned = match owned {
+                    Ok(owned) => owned,
+                    Err(e) => {
+                        tracing::error!("Unable to load positions of {}: {e}", wallet.name);
+                        break;
+                    }
+                };
+                let realized_pnl = cloned_state
+                    .journal
+                    .lock()
+                    .unwrap()
+                    .realized_pnl(&wallet.pubkey(), cloned_state.config.paper_trading);
+                infos.push(WalletInfo {
+                    name: wallet.name.clone(),
+                    pubkey: wallet.pubkey(),
+                    balance: b,
+                    owned_tokens: owned,
+                    realized_pnl,
+                });
+            }
+            // Only act on a complete view of every wallet
+            if infos.len() == cloned_state.wallets.all().len() {
+                let owned: Vec<Position> = infos
+                    .iter()
+                    .flat_map(|info| info.owned_tokens.iter().cloned())
+                    .collect();
+                check_auto_sell(&owned, cloned_state.clone());
+                tx.send(infos).unwrap();
+            }
             sleep(Duration::from_secs(5)).await;
         }
     });
@@ -139,6 +189,7 @@ pub async fn run_ui(
 
             let main_block = Block::default()
                 .title("lytra v1.0.3")
+                .title(Title::from(monitor_health_line(&state)).alignment(Alignment::Right))
                 .borders(Borders::ALL)
                 .style(Style::default().fg(Color::White).bg(Color::Black));
 
@@ -222,12 +273,34 @@ pub async fn run_ui(
                 .split(chunks[1]);
 
             let current_time = Local::now();
+            let mode = if state.config.paper_trading {
+                Line::from(Span::styled(
+                    "Mode: PAPER TRADING (simulated)",
+                    Style::default()
+                        .fg(Color::Yellow)
+                        .add_modifier(Modifier::BOLD),
+                ))
+            } else {
+                Line::from("Mode: LIVE")
+            };
+            let wallet_info_state = &wallet_infos[selected_wallet];
             let wallet_info = Text::from(vec![
-                Line::from(format!("Public Key: {}", keypair.pubkey())),
+                mode,
+                Line::from(format!(
+                    "Wallet: {} ({}/{})",
+                    wallet_info_state.name,
+                    selected_wallet + 1,
+                    wallet_infos.len()
+                )),
+                Line::from(format!("Public Key: {}", wallet_info_state.pubkey)),
                 Line::from(format!(
                     "SOL Balance: {} SOL",
                     wallet_info_state.balance as f64 / 1_000_000_000.0
                 )),
+                Line::from(format!(
+                    "Realized PnL: {:.5} SOL",
+                    wallet_info_state.realized_pnl
+                )),
                 Line::from(format!(
                     "Current Time: {}",
                     current_time.format("%Y-%m-%d %H:%M:%S")
@@ -257,11 +330,24 @@ pub async fn run_ui(
                     } else {
                         Style::default()
                     };
+                    let symbol = if token.simulated {
+                        format!("[SIM] {}", token.symbol)
+                    } else {
+                        token.symbol.clone()
+                    };
                     Row::new(vec![
-                        Cell::from(Span::raw(token.0.clone())).style(style),
-                        Cell::from(Span::raw(token.1.clone())).style(style),
-                        Cell::from(Span::raw(token.2.clone())).style(style),
-                        Cell::from(Span::raw(token.3.clone())).style(style),
+                        Cell::from(Span::raw(symbol)).style(style),
+                        Cell::from(Span::raw(format!("{:.5}", token.cost_basis))).style(style),
+                        Cell::from(Span::raw(
+                            token
+                                .value_sol
+                                .map_or("-".into(), |value| format!("{value:.5}")),
+                        ))
+                        .style(style),
+                        Cell::from(Span::raw(
+                            token.pnl_pct.map_or("-".into(), |pnl| format!("{pnl:.2}")),
+                        ))
+                        .style(style),
                     ])
                 })
                 .collect();
@@ -303,6 +389,7 @@ pub async fn run_ui(
                 Line::from("Press 'q' to quit"),
                 Line::from("Press 's' to sell selection"),
                 Line::from("Use Up/Down arrows to navigate"),
+                Line::from("Use Left/Right arrows to switch wallet"),
             ]);
             let help_paragraph = Paragraph::new(help_message)
                 .block(
@@ -334,8 +421,8 @@ pub async fn run_ui(
         }
 
         if let Ok(new_state) = rx.try_recv() {
-            wallet_info_state = new_state;
-            stateful_wallet_table.items = wallet_info_state.owned_tokens.clone();
+            wallet_infos = new_state;
+            stateful_wallet_table.items = wallet_infos[selected_wallet].owned_tokens.clone();
         }
 
         if crossterm::event::poll(Duration::from_millis(100))? {
@@ -344,28 +431,40 @@ pub async fn run_ui(
                     KeyCode::Char('q') => break,
                     KeyCode::Down => stateful_wallet_table.next(),
                     KeyCode::Up => stateful_wallet_table.previous(),
+                    KeyCode::Left | KeyCode::Right => {
+                        let count = wallet_infos.len();
+                        selected_wallet = if key.code == KeyCode::Right {
+                            (selected_wallet + 1) % count
+                        } else {
+                            (selected_wallet + count - 1) % count
+                        };
+                        stateful_wallet_table.items =
+                            wallet_infos[selected_wallet].owned_tokens.clone();
+                        stateful_wallet_table.state.select(None);
+                    }
                     KeyCode::Char('s') => {
                         show_confirmation = true;
                     }
-                    KeyCode::Char('y') => {
-                        if show_confirmation {
-                            show_confirmation = false;
+                    KeyCode::Char('y') if show_confirmation => {
+                        show_confirmation = false;
+                        // Nothing is selected right after switching wallets
+                        let row = stateful_wallet_table
+                            .state
+                            .selected()
+                            .and_then(|i| stateful_wallet_table.items.get(i))
+                            .cloned();
+                        if let Some(row) = row {
                             let cloned_state = state.clone();
-                            let i = stateful_wallet_table.state.selected().unwrap();
-                            let row = wallet_info_state.owned_tokens.get(i).unwrap().clone();
                             tokio::spawn(async move {
-                                let token = row.4.clone();
-                                let amount = row.5.clone();
-                                tasks::sell_token_task(token, amount, cloned_state)
-                                    .await
-                                    .unwrap();
+                                let token = row.mint.clone();
+                                let amount = row.amount;
+                                tasks::sell_token_task(row.wallet, token, amount, cloned_state)
+                                    .await;
                             });
                         }
                     }
                     KeyCode::Char('n') => {
-                        if show_confirmation {
-                            show_confirmation = false;
-                        }
+                        show_confirmation = false;
                     }
                     _ => {}
                 }
@@ -381,72 +480,107 @@ pub async fn run_ui(
     Ok(())
 }
 
-pub async fn get_owned_tokens(
-    public_key: String,
-) -> Result<Vec<(String, String, String, String, String, f64)>, Box<dyn Error>> {
-    let mut owned_tokens: Vec<(String, String, String, String, String, f64)> = vec![];
-
-    let response = reqwest::Client::builder()
-        .build()?
-        .get(format!("https://wallet-api.solflare.com/v3/portfolio/tokens/{public_key}?network=mainnet&currency=USD"))
-        .header("Accept", "application/json")
-        .send()
-        .await?;
-
-    if response.status() == reqwest::StatusCode::OK {
-        let res: Value = response.json().await?;
-
-        if let Some(tokens) = res["tokens"].as_array() {
-            for token in tokens {
-                let symbol = token["symbol"].as_str().unwrap().to_string();
-                if symbol == "SOL" {
-                    continue;
-                }
-                let token_amount = token["totalUiAmount"].as_f64().unwrap();
-                if token_amount < 0.0000001 {
-                    continue;
-                }
-
-                let mint = token["mint"].as_str().unwrap().to_string();
-                let price_sol = token["solPrice"]["price"].as_f64().unwrap_or(0.0);
-                let current_sol_worth = token_amount * price_sol;
-                let initial_investment = 0.01;
-
-                let profit_loss: f64 = if initial_investment > 0.0 {
-                    ((current_sol_worth / initial_investment) - 1.0) * 100.0
-                } else {
-                    0.0
-                };
-
-                owned_tokens.push((
-                    symbol,
-                    format!("{initial_investment:.5}"),
-                    format!("{current_sol_worth:.5}"),
-                    format!("{profit_loss:.2}"),
-                    mint,
-                    token_amount,
-                ));
-            }
-        }
+// Seconds without a successful poll before a running source is shown as stale
+const STALE_POLL_SECS: i64 = 60;
+
+// One " name: state " span per signal source, colored by health
+fn monitor_health_line(state: &State) -> Line<'static> {
+    let health = state.monitor_health.lock().unwrap();
+    if health.is_empty() {
+        return Line::from(Span::styled(
+            " monitor down: no signal source running ",
+            Style::default().fg(Color::Red),
+        ));
     }
 
-    Ok(owned_tokens)
+    let now = chrono::Utc::now().timestamp();
+    let spans: Vec<Span> = health
+        .iter()
+        .map(|(name, source)| {
+            let since_poll = source.last_poll.map(|t| now - t);
+            let (text, color) = match &source.status {
+                SourceStatus::Starting => (format!("{name}: starting"), Color::Yellow),
+                SourceStatus::Running => {
+                    let ago = since_poll.unwrap_or_default();
+                    let color = if ago > STALE_POLL_SECS {
+                        Color::Yellow
+                    } else {
+                        Color::Green
+                    };
+                    (format!("{name}: last successful poll {ago}s ago"), color)
+                }
+                SourceStatus::Reconnecting { error, retry_at } => (
+                    format!(
+                        "{name}: monitor down ({error}), reconnecting in {}s",
+                        (retry_at - now).max(0)
+                    ),
+                    Color::Red,
+                ),
+            };
+            Span::styled(format!(" {text} "), Style::default().fg(color))
+        })
+        .collect();
+    Line::from(spans)
 }
 
-pub fn check_auto_sell(owned: Vec<(String, String, String, String, String, f64)>, state: State) {
-    if state.config.sell_config.auto_sell {
-        for token in owned {
-            if token.3.parse::<f64>().unwrap() >= state.config.sell_config.sell_at {
-                let cloned_state = state.clone();
-                tokio::spawn(async move {
-                    let t = token.4.clone();
-                    let amount = token.5.clone();
-                    tasks::sell_token_task(t, amount, cloned_state)
-                        .await
-                        .unwrap();
-                });
-            }
-        }
+pub fn check_auto_sell(owned: &[Position], state: State) {
+    let positions: HashMap<String, PositionBook> = {
+        let journal = state.journal.lock().unwrap();
+        state
+            .wallets
+            .all()
+            .iter()
+            .flat_map(|w| journal.open_positions(&w.pubkey(), state.config.paper_trading))
+            .map(|p| (position_key(&p.wallet, &p.mint), p))
+            .collect()
+    };
+    let held: Vec<String> = positions.keys().cloned().collect();
+
+    let orders = {
+        let mut tracker = state.exit_tracker.lock().unwrap();
+        tracker.retain(&held);
+        owned
+            .iter()
+            .filter_map(|token| {
+                // Unpriced positions would look like a total loss
+                let value_sol = token.value_sol?;
+                let user = positions
+                    .get(&position_key(&token.wallet, &token.mint))
+                    .map(|p| p.user.as_str())?;
+                let sell_config = state.config.sell_config_for(user);
+                if !sell_config.auto_sell {
+                    return None;
+                }
+                tracker.evaluate(
+                    &sell_config,
+                    &token.wallet,
+                    &token.mint,
+                    token.amount,
+                    token.cost_basis,
+                    value_sol,
+                )
+            })
+            .collect::<Vec<_>>()
+    };
+
+    for order in orders {
+        log::warn!(target:"app", "Auto sell {}: {}", order.mint, order.reason);
+        let cloned_state = state.clone();
+        tokio::spawn(async move {
+            let (wallet, mint) = (order.wallet.clone(), order.mint.clone());
+            let landed = tasks::sell_token_task(
+                order.wallet,
+                order.mint,
+                order.tokens,
+                cloned_state.clone(),
+            )
+            .await;
+            cloned_state
+                .exit_tracker
+                .lock()
+                .unwrap()
+                .exit_finished(&wallet, &mint, landed);
+        });
     }
 }
 
@@ -0,0 +1,66 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use solana_sdk::{signature::Keypair, signer::Signer};
+
+use crate::config::{Config, WalletAssignment};
+
+pub struct Wallet {
+    pub name: String,
+    pub keypair: Keypair,
+}
+
+impl Wallet {
+    pub fn pubkey(&self) -> String {
+        self.keypair.pubkey().to_string()
+    }
+}
+
+// Every trading wallet, and which one the next buy of each user goes through
+#[derive(Clone)]
+pub struct Wallets {
+    wallets: Arc<Vec<Wallet>>,
+    next: Arc<AtomicUsize>,
+}
+
+impl Wallets {
+    pub fn new(wallets: Vec<Wallet>) -> Self {
+        assert!(!wallets.is_empty(), "at least one wallet is required");
+        Self {
+            wallets: Arc::new(wallets),
+            next: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    pub fn all(&self) -> &[Wallet] {
+        &self.wallets
+    }
+
+    // The wallet legacy trades and unassigned users fall back to
+    pub fn default_wallet(&self) -> &Wallet {
+        &self.wallets[0]
+    }
+
+    pub fn by_pubkey(&self, pubkey: &str) -> Option<&Wallet> {
+        self.wallets.iter().find(|w| w.pubkey() == pubkey)
+    }
+
+    // A user mapped to a wallet always buys with it, others follow the assignment mode
+    pub fn for_buy(&self, config: &Config, user: &str) -> &Wallet {
+        if let Some(name) = config.wallet_for(user) {
+            match self.wallets.iter().find(|w| w.name == name) {
+                Some(wallet) => return wallet,
+                None => tracing::error!("Unknown wallet {name} for {user}, using assignment mode"),
+            }
+        }
+        match config.wallet_assignment {
+            WalletAssignment::Default => self.default_wallet(),
+            WalletAssignment::RoundRobin => {
+                let i = self.next.fetch_add(1, Ordering::Relaxed);
+                &self.wallets[i % self.wallets.len()]
+            }
+        }
+    }
+}
@@ -0,0 +1,424 @@
+use std::{collections::HashMap, error::Error, time::Duration};
+
+use futures::{future::BoxFuture, StreamExt};
+use tokio::{
+    sync::mpsc::UnboundedSender,
+    time::{sleep, Instant},
+};
+use twitter_v2::{
+    authorization::BearerToken,
+    data::{Expansions, ReferencedTweetKind},
+    id::NumericId,
+    query::{TweetExpansion, TweetField},
+    Tweet, TwitterApi,
+};
+
+use crate::{
+    config::{XApiConfig, XApiMode},
+    signals::{HealthReporter, Signal, SignalSource, TweetKind},
+};
+
+// Tag prefix of the stream rules this source owns, other rules on the same token are left alone
+const RULE_TAG_PREFIX: &str = "sloppy-twitter:";
+// Length limit of a filtered stream rule on the basic access level
+const MAX_RULE_LEN: usize = 512;
+// Largest timeline page, and pages read per poll before the rest is left for the next one
+const PAGE_SIZE: usize = 100;
+const MAX_PAGES: usize = 5;
+
+// Watches the configured users through the official X API v2 instead of the web client
+pub struct XApiSource {
+    config: XApiConfig,
+    users: Vec<String>,
+}
+
+impl XApiSource {
+    pub fn new(config: XApiConfig, users: Vec<String>) -> Self {
+        Self { config, users }
+    }
+}
+
+impl SignalSource for XApiSource {
+    fn name(&self) -> &'static str {
+        "x_api"
+    }
+
+    fn run(
+        &self,
+        signals: UnboundedSender<Signal>,
+        health: HealthReporter,
+    ) -> BoxFuture<'static, Result<(), Box<dyn Error + Send + Sync>>> {
+        Box::pin(monitor(
+            signals,
+            health,
+            self.config.clone(),
+            self.users.clone(),
+        ))
+    }
+}
+
+async fn monitor(
+    signals: UnboundedSender<Signal>,
+    health: HealthReporter,
+    config: XApiConfig,
+    users: Vec<String>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let api = TwitterApi::new(BearerToken::new(config.bearer_token.clone()));
+
+    let mut usernames: HashMap<NumericId, String> = HashMap::new();
+    for u in users.iter() {
+        let user = api
+            .get_user_by_username(u)
+            .send()
+            .await?
+            .into_data()
+            .ok_or(format!("X API user not found: {u}"))?;
+        usernames.insert(user.id, u.to_string());
+    }
+
+    log::info!(target:"app", "X API monitor initialized and ready!");
+
+    match config.mode {
+        XApiMode::Poll => poll_timelines(&api, &config, &signals, &health, &usernames).await,
+        XApiMode::Stream => filtered_stream(&api, &signals, &health, &usernames).await,
+    }
+}
+
+// Poll each user's timeline, only tweets newer than the last seen id are reported
+async fn poll_timelines(
+    api: &TwitterApi<BearerToken>,
+    config: &XApiConfig,
+    signals: &UnboundedSender<Signal>,
+    health: &HealthReporter,
+    usernames: &HashMap<NumericId, String>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut last_seen: HashMap<NumericId, NumericId> = HashMap::new();
+    let interval = Duration::from_secs(config.poll_interval_secs.max(1));
+
+    // Only remember the latest tweet on the first pass, like the web monitor
+    for id in usernames.keys() {
+        if let Some(tweet) = latest_tweets(api, *id, None).await?.0.into_iter().next() {
+            last_seen.insert(*id, tweet.id);
+        }
+    }
+    health.polled();
+
+    loop {
+        let start = Instant::now();
+        let mut failed = false;
+        for (id, username) in usernames.iter() {
+            let (tweets, included) = match latest_tweets(api, *id, last_seen.get(id).cloned()).await
+            {
+                Ok(found) => found,
+                Err(e) => {
+                    tracing::error!("X API timeline error: {e}");
+                    failed = true;
+                    continue;
+                }
+            };
+            if let Some(newest) = tweets.first() {
+                last_seen.insert(*id, newest.id);
+            }
+            // Oldest first so the status column ends on the newest tweet
+            for tweet in tweets.into_iter().rev() {
+                signals.send(to_signal(username, tweet, &included))?;
+            }
+        }
+        if !failed {
+            health.polled();
+        }
+
+        let elapsed = start.elapsed();
+        if elapsed < interval {
+            sleep(interval - elapsed).await;
+        }
+    }
+}
+
+// Newest first, along with the referenced tweets they include. Without a since id only the
+// latest page is read, with one every page back to it.
+async fn latest_tweets(
+    api: &TwitterApi<BearerToken>,
+    user_id: NumericId,
+    since_id: Option<NumericId>,
+) -> Result<(Vec<Tweet>, Vec<Tweet>), Box<dyn Error + Send + Sync>> {
+    let mut tweets = vec![];
+    let mut included = vec![];
+    let mut next_token: Option<String> = None;
+    for _ in 0..MAX_PAGES {
+        let mut req = api.get_user_tweets(user_id);
+        req.max_results(if since_id.is_some() { PAGE_SIZE } else { 5 })
+            .tweet_fields([
+                TweetField::AuthorId,
+                TweetField::CreatedAt,
+                TweetField::Entities,
+                TweetField::ReferencedTweets,
+            ])
+            .expansions([TweetExpansion::ReferencedTweetsId]);
+        if let Some(since_id) = since_id {
+            req.since_id(since_id);
+        }
+        if let Some(token) = next_token.as_deref() {
+            req.pagination_token(token);
+        }
+        let response = req.send().await?;
+        included.extend(included_tweets(response.includes()));
+        next_token = response.meta().and_then(|meta| meta.next_token.clone());
+        tweets.extend(response.into_data().unwrap_or_default());
+        if since_id.is_none() || next_token.is_none() {
+            break;
+        }
+    }
+    Ok((tweets, included))
+}
+
+fn included_tweets(includes: Option<&Expansions>) -> Vec<Tweet> {
+    includes
+        .and_then(|includes| includes.tweets.clone())
+        .unwrap_or_default()
+}
+
+// Users combined into `from:a OR from:b` rules, each within the rule length limit
+fn stream_rules<'a>(usernames: impl Iterator<Item = &'a String>) -> Vec<String> {
+    let mut rules: Vec<String> = vec![];
+    for username in usernames {
+        let clause = format!("from:{username}");
+        match rules.last_mut() {
+            Some(rule) if rule.len() + " OR ".len() + clause.len() <= MAX_RULE_LEN => {
+                rule.push_str(" OR ");
+                rule.push_str(&clause);
+            }
+            _ => rules.push(clause),
+        }
+    }
+    rules
+}
+
+// Replace the rules of this source with `from:` rules for the users and consume the filtered stream
+async fn filtered_stream(
+    api: &TwitterApi<BearerToken>,
+    signals: &UnboundedSender<Signal>,
+    health: &HealthReporter,
+    usernames: &HashMap<NumericId, String>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let existing = api
+        .get_tweets_search_stream_rules()
+        .send()
+        .await?
+        .into_data()
+        .unwrap_or_default();
+    let owned: Vec<_> = existing
+        .into_iter()
+        .filter(|rule| {
+            rule.tag
+                .as_deref()
+                .is_some_and(|tag| tag.starts_with(RULE_TAG_PREFIX))
+        })
+        .collect();
+    if !owned.is_empty() {
+        let mut req = api.post_tweets_search_stream_rule();
+        for rule in owned {
+            req.delete_id(rule.id);
+        }
+        req.send().await?;
+    }
+
+    let mut req = api.post_tweets_search_stream_rule();
+    for (i, rule) in stream_rules(usernames.values()).into_iter().enumerate() {
+        req.add_tagged(rule, format!("{RULE_TAG_PREFIX}{i}"));
+    }
+    req.send().await?;
+
+    let mut stream = api
+        .get_tweets_search_stream()
+        .tweet_fields([
+            TweetField::AuthorId,
+            TweetField::CreatedAt,
+            TweetField::Entities,
+            TweetField::ReferencedTweets,
+        ])
+        .expansions([TweetExpansion::ReferencedTweetsId])
+        .stream()
+        .await?;
+    health.polled();
+
+    while let Some(payload) = stream.next().await {
+        let payload = match payload {
+            Ok(payload) => payload,
+            Err(e) => {
+                tracing::error!("X API stream error: {e}");
+                continue;
+            }
+        };
+        health.polled();
+        let included = included_tweets(payload.includes());
+        let Some(tweet) = payload.into_data() else {
+            continue;
+        };
+        let Some(username) = tweet.author_id.and_then(|id| usernames.get(&id)) else {
+            continue;
+        };
+        signals.send(to_signal(username, tweet, &included))?;
+    }
+
+    Err("X API stream closed".into())
+}
+
+// `included` holds the referenced tweets the response expanded
+fn to_signal(username: &str, tweet: Tweet, included: &[Tweet]) -> Signal {
+    let mut signal = Signal::new(
+        "x_api",
+        tweet.id.to_string(),
+        username.to_string(),
+        tweet.text,
+    );
+    if let Some(created_at) = tweet.created_at {
+        signal.timestamp = created_at.unix_timestamp();
+    }
+
+    let references = tweet.referenced_tweets.unwrap_or_default();
+    let referenced = |kind| references.iter().find(|r| r.kind == kind);
+    signal.kind = if referenced(ReferencedTweetKind::Retweeted).is_some() {
+        TweetKind::Retweet
+    } else if referenced(ReferencedTweetKind::Quoted).is_some() {
+        TweetKind::Quote
+    } else if referenced(ReferencedTweetKind::RepliedTo).is_some() {
+        TweetKind::Reply
+    } else {
+        TweetKind::Original
+    };
+    signal.quoted_text = referenced(ReferencedTweetKind::Quoted)
+        .and_then(|quoted| included.iter().find(|t| t.id == quoted.id))
+        .map(|quoted| quoted.text.clone());
+
+    if let Some(entities) = tweet.entities {
+        let urls: Vec<String> = entities
+            .urls
+            .unwrap_or_default()
+            .into_iter()
+            .map(|u| u.expanded_url)
+            .collect();
+        if !urls.is_empty() {
+            signal.urls = urls;
+        }
+        signal.cashtags = entities
+            .cashtags
+            .unwrap_or_default()
+            .into_iter()
+            .map(|c| c.tag)
+            .collect();
+    }
+    signal
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn tweet(value: serde_json::Value) -> Tweet {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn quote_with_link() {
+        let quote = tweet(json!({
+            "id": "1800000000000000002",
+            "text": "this one $MOON https://t.co/abc",
+            "author_id": "42",
+            "created_at": "2024-06-01T12:00:00.000Z",
+            "referenced_tweets": [{ "type": "quoted", "id": "1800000000000000001" }],
+            "entities": {
+                "urls": [{
+                    "start": 15,
+                    "end": 38,
+                    "url": "https://t.co/abc",
+                    "expanded_url": "https://pump.fun/coin/example",
+                    "display_url": "pump.fun/coin/example"
+                }],
+                "cashtags": [{ "start": 9, "end": 14, "tag": "MOON" }]
+            }
+        }));
+        let included = [
+            tweet(json!({ "id": "1800000000000000009", "text": "unrelated" })),
+            tweet(json!({ "id": "1800000000000000001", "text": "CA in the quoted tweet" })),
+        ];
+
+        let signal = to_signal("alice", quote, &included);
+        assert_eq!(signal.source, "x_api");
+        assert_eq!(signal.id, "1800000000000000002");
+        assert_eq!(signal.author, "alice");
+        assert_eq!(signal.kind, TweetKind::Quote);
+        assert_eq!(
+            signal.quoted_text.as_deref(),
+            Some("CA in the quoted tweet")
+        );
+        assert_eq!(
+            signal.urls,
+            vec!["https://pump.fun/coin/example".to_string()]
+        );
+        assert_eq!(signal.cashtags, vec!["MOON".to_string()]);
+        assert_eq!(signal.timestamp, 1717243200);
+    }
+
+    #[test]
+    fn quote_without_expansion() {
+        // The quoted tweet may be missing from the includes, e.g. when it was deleted
+        let quote = tweet(json!({
+            "id": "2",
+            "text": "look",
+            "referenced_tweets": [{ "type": "quoted", "id": "1" }]
+        }));
+        let signal = to_signal("alice", quote, &[]);
+        assert_eq!(signal.kind, TweetKind::Quote);
+        assert_eq!(signal.quoted_text, None);
+    }
+
+    #[test]
+    fn rules_pack_users_within_the_limit() {
+        let usernames: Vec<String> = (0..60).map(|i| format!("user_{i:03}")).collect();
+        let rules = stream_rules(usernames.iter());
+
+        // "from:user_000" is 13 characters, 30 of them and 29 " OR " make 506
+        assert_eq!(rules.len(), 2);
+        assert!(rules.iter().all(|rule| rule.len() <= MAX_RULE_LEN));
+        assert_eq!(rules[0].len(), 506);
+        assert!(rules[0].starts_with("from:user_000 OR from:user_001"));
+        assert!(rules[1].starts_with("from:user_030 OR "));
+        assert!(rules[1].ends_with(" OR from:user_059"));
+
+        let clauses: Vec<&str> = rules.iter().flat_map(|rule| rule.split(" OR ")).collect();
+        let expected: Vec<String> = usernames.iter().map(|u| format!("from:{u}")).collect();
+        assert_eq!(clauses, expected);
+    }
+
+    #[test]
+    fn rules_edge_cases() {
+        assert!(stream_rules([].iter()).is_empty());
+        assert_eq!(stream_rules(["alice".to_string()].iter()), ["from:alice"]);
+
+        // A rule filled exactly to the limit is kept, one more character starts a new rule
+        let exact = "a".repeat(MAX_RULE_LEN - "from:".len() * 2 - " OR ".len() - 1);
+        let users = [exact.clone(), "b".to_string()];
+        assert_eq!(stream_rules(users.iter()).len(), 1);
+        let users = [exact + "a", "b".to_string()];
+        assert_eq!(stream_rules(users.iter()).len(), 2);
+    }
+
+    #[test]
+    fn tweet_kinds() {
+        let kind = |references: serde_json::Value| {
+            let tweet = tweet(json!({ "id": "2", "text": "gm", "referenced_tweets": references }));
+            to_signal("alice", tweet, &[]).kind
+        };
+        assert_eq!(kind(json!([])), TweetKind::Original);
+        assert_eq!(
+            kind(json!([{ "type": "replied_to", "id": "1" }])),
+            TweetKind::Reply
+        );
+        assert_eq!(
+            kind(json!([{ "type": "retweeted", "id": "1" }])),
+            TweetKind::Retweet
+        );
+    }
+}